@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::thread::grapheme_len;
+
+/// Longest teaser (in graphemes, link included); Bluesky's post limit, the smallest.
+const TEASER_MAX_GRAPHEMES: usize = 300;
+
+/// A long-form Markdown article, published to Nostr as a NIP-23 event and announced
+/// on the other networks with a teaser that links to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Article {
+    pub title: String,
+    /// Markdown body.
+    pub content: String,
+    pub summary: Option<String>,
+    /// NIP-23 `d` identifier; publishing again with the same one replaces the article.
+    pub identifier: String,
+    /// Header image URL.
+    pub image: Option<String>,
+    /// When the article was first published; defaults to now. Keep the original time
+    /// when republishing an edit.
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+impl Article {
+    /// An article identified by a slug of `title`.
+    pub fn new(title: impl Into<String>, content: impl Into<String>) -> Self {
+        let title = title.into();
+        Self {
+            identifier: slugify(&title),
+            title,
+            content: content.into(),
+            summary: None,
+            image: None,
+            published_at: None,
+        }
+    }
+
+    pub fn with_summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    pub fn with_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.identifier = identifier.into();
+        self
+    }
+
+    pub fn with_image(mut self, url: impl Into<String>) -> Self {
+        self.image = Some(url.into());
+        self
+    }
+
+    pub fn with_published_at(mut self, published_at: DateTime<Utc>) -> Self {
+        self.published_at = Some(published_at);
+        self
+    }
+
+    /// Teaser text ending in `link`: `text` if given, otherwise the title and summary.
+    ///
+    /// The text is shortened with an ellipsis so the whole teaser fits in one post on
+    /// every network.
+    pub fn teaser(&self, text: Option<&str>, link: &str) -> String {
+        let text = match (text, &self.summary) {
+            (Some(text), _) => text.trim().to_string(),
+            (None, Some(summary)) => format!("{}\n\n{}", self.title, summary.trim()),
+            (None, None) => self.title.clone(),
+        };
+
+        let budget = TEASER_MAX_GRAPHEMES.saturating_sub(grapheme_len(link) + 2);
+        let text = if grapheme_len(&text) > budget {
+            let mut short: String = text
+                .graphemes(true)
+                .take(budget.saturating_sub(1))
+                .collect();
+            short.truncate(short.trim_end().len());
+            short.push('…');
+            short
+        } else {
+            text
+        };
+        format!("{text}\n\n{link}")
+    }
+}
+
+/// Lowercase words of `title` joined by hyphens, e.g. `release-notes-for-0-3`.
+fn slugify(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        Utc::now().format("%Y%m%d%H%M%S").to_string()
+    } else {
+        slug
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: &str = "https://njump.me/naddr1example";
+
+    #[test]
+    fn teaser_that_fits_is_title_and_summary() {
+        let article = Article::new("Release notes", "body").with_summary("  What changed.  ");
+        assert_eq!(
+            article.teaser(None, LINK),
+            format!("Release notes\n\nWhat changed.\n\n{LINK}")
+        );
+    }
+
+    #[test]
+    fn long_teaser_is_cut_to_one_post_with_an_ellipsis() {
+        let article = Article::new("Release notes", "body");
+        let teaser = article.teaser(Some(&"a".repeat(400)), LINK);
+
+        assert_eq!(grapheme_len(&teaser), TEASER_MAX_GRAPHEMES);
+        let (text, link) = teaser.split_once("\n\n").unwrap();
+        assert!(text.ends_with("a…"), "{text:?}");
+        assert_eq!(link, LINK);
+    }
+
+    #[test]
+    fn teaser_cut_after_a_space_drops_the_space() {
+        let article = Article::new("Release notes", "body");
+        let budget = TEASER_MAX_GRAPHEMES - grapheme_len(LINK) - 2;
+        let kept = "a".repeat(budget - 2);
+        let teaser = article.teaser(Some(&format!("{kept} {}", "b".repeat(100))), LINK);
+
+        assert_eq!(teaser, format!("{kept}…\n\n{LINK}"));
+    }
+
+    #[test]
+    fn teaser_is_cut_between_graphemes() {
+        let article = Article::new("Release notes", "body");
+        let text = "👩‍👩‍👧".repeat(400);
+        let teaser = article.teaser(Some(&text), LINK);
+
+        let budget = TEASER_MAX_GRAPHEMES - grapheme_len(LINK) - 2;
+        assert_eq!(teaser, format!("{}…\n\n{LINK}", "👩‍👩‍👧".repeat(budget - 1)));
+    }
+
+    #[test]
+    fn slug_joins_lowercase_words() {
+        assert_eq!(slugify("Release Notes for 0.3!"), "release-notes-for-0-3");
+        assert_eq!(slugify("  Ünïcode — Titles  "), "ünïcode-titles");
+    }
+
+    #[test]
+    fn title_without_words_gets_a_timestamp_slug() {
+        let slug = slugify("?!");
+        assert_eq!(slug.len(), "20261016120000".len());
+        assert!(slug.chars().all(|c| c.is_ascii_digit()), "{slug:?}");
+    }
+}
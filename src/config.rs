@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+
+/// Merged view of process environment variables and `config.env` defaults.
+///
+/// Environment variables always win over values from the config file.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    defaults: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let defaults = load_config_defaults();
+
+        #[cfg(debug_assertions)]
+        {
+            let _ = dotenvy::dotenv();
+        }
+
+        Self { defaults }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        std::env::var(key)
+            .ok()
+            .or_else(|| self.defaults.get(key).cloned())
+    }
+
+    /// Reads a comma-separated value, dropping blank entries.
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .map(|s| {
+                s.split(',')
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Platform-specific config and data directories for `dist`.
+pub fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "dist")
+}
+
+/// Writes `bytes` to `path` atomically, readable only by the current user. For
+/// credentials such as cached sessions and keys.
+pub(crate) fn write_private(path: &Path, label: &str, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("{label}: create {}", parent.display()))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(tmp)
+        .with_context(|| format!("{label}: write {}", tmp.display()))?;
+    file.write_all(bytes)
+        .with_context(|| format!("{label}: write {}", tmp.display()))?;
+    std::fs::rename(tmp, path).with_context(|| format!("{label}: write {}", path.display()))
+}
+
+fn load_config_defaults() -> HashMap<String, String> {
+    let mut values = HashMap::new();
+
+    if let Some(dirs) = project_dirs() {
+        let config_path = dirs.config_dir().join("config.env");
+        if let Ok(iter) = dotenvy::from_path_iter(&config_path) {
+            for item in iter.flatten() {
+                values.insert(item.0, item.1);
+            }
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_files_replace_the_old_copy_for_the_owner_only() {
+        let dir = std::env::temp_dir().join(format!("dist-config-{}", std::process::id()));
+        let path = dir.join("secret.key");
+        write_private(&path, "test", b"old").unwrap();
+        write_private(&path, "test", b"new").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
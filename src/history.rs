@@ -0,0 +1,454 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::config::project_dirs;
+use crate::post::{Focus, Image, Poll, Post, PostOptions};
+use crate::services::{Outcome, Receipt, ServiceResult};
+
+/// Local record of every cross-post, stored as JSON in the user data dir.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+}
+
+/// One `dist` invocation and what each network returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+    pub text: String,
+    /// Whether the post was published with `--thread`.
+    #[serde(default)]
+    pub thread: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageRecord>,
+    #[serde(default, skip_serializing_if = "PostOptions::is_empty")]
+    pub options: PostOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<Poll>,
+    pub services: Vec<ServiceRecord>,
+}
+
+/// An attachment copied into the history's `media` dir so it can be re-sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageRecord {
+    pub path: PathBuf,
+    pub mime_type: String,
+    pub alt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus: Option<Focus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceRecord {
+    /// Service key, e.g. `bsky`.
+    pub service: String,
+    #[serde(flatten)]
+    pub status: RecordStatus,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub receipts: Vec<Receipt>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum RecordStatus {
+    Posted,
+    Failed { error: String },
+    Deleted { deleted_at: DateTime<Utc> },
+}
+
+impl History {
+    /// Opens `history.json` in the platform data dir.
+    pub fn open_default() -> Result<Self> {
+        let dirs = project_dirs().ok_or_else(|| anyhow!("history: no home directory"))?;
+        Self::open(dirs.data_dir().join("history.json"))
+    }
+
+    /// Opens the history at `path`; a missing file is an empty history.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = read_store(&path, "history")?;
+        Ok(Self { path, entries })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Entries whose text or identifiers contain `query` (case-insensitive), newest first.
+    pub fn search<'a>(&'a self, query: &str) -> impl Iterator<Item = &'a HistoryEntry> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .rev()
+            .filter(move |entry| entry.matches(&query))
+    }
+
+    /// Appends an entry for `results` and writes the history to disk.
+    ///
+    /// Skipped services are not recorded. Returns `None` when every service was skipped.
+    pub fn record(
+        &mut self,
+        post: &Post,
+        thread: bool,
+        results: &[ServiceResult],
+    ) -> Result<Option<u64>> {
+        let services: Vec<ServiceRecord> = results
+            .iter()
+            .filter_map(ServiceRecord::from_result)
+            .collect();
+
+        if services.is_empty() {
+            return Ok(None);
+        }
+
+        let path = self.path.clone();
+        self.modify(|entries| {
+            let id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+            let images = store_media(&path, &id.to_string(), &post.images).context("history")?;
+            entries.push(HistoryEntry {
+                id,
+                created_at: Utc::now(),
+                text: post.text.clone(),
+                thread,
+                images,
+                options: post.options.clone(),
+                poll: post.poll.clone(),
+                services,
+            });
+            Ok(Some(id))
+        })
+    }
+
+    /// Replaces the record for `result`'s service in entry `id` and writes the history.
+    ///
+    /// Skipped results leave the entry unchanged.
+    pub fn update(&mut self, id: u64, result: &ServiceResult) -> Result<()> {
+        let Some(record) = ServiceRecord::from_result(result) else {
+            return Ok(());
+        };
+        self.modify(|entries| {
+            let entry = entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow!("history: no entry #{id}"))?;
+            match entry
+                .services
+                .iter_mut()
+                .find(|s| s.service == record.service)
+            {
+                Some(existing) => *existing = record,
+                None => entry.services.push(record),
+            }
+            Ok(())
+        })
+    }
+
+    /// Marks `service` of entry `id` as deleted and writes the history to disk.
+    pub fn mark_deleted(&mut self, id: u64, service: &str) -> Result<()> {
+        self.modify(|entries| {
+            service_record(entries, id, service)?.status = RecordStatus::Deleted {
+                deleted_at: Utc::now(),
+            };
+            Ok(())
+        })
+    }
+
+    /// Drops `receipts` from `service`'s record in entry `id` and writes the history,
+    /// once those parts are deleted from the network.
+    pub fn remove_receipts(&mut self, id: u64, service: &str, receipts: &[Receipt]) -> Result<()> {
+        self.modify(|entries| {
+            service_record(entries, id, service)?
+                .receipts
+                .retain(|r| !receipts.contains(r));
+            Ok(())
+        })
+    }
+
+    /// Applies `change` to the entries on disk and writes them back, holding the store
+    /// lock throughout so concurrent `dist` processes don't drop each other's changes.
+    fn modify<R>(&mut self, change: impl FnOnce(&mut Vec<HistoryEntry>) -> Result<R>) -> Result<R> {
+        let _lock = lock_store(&self.path, "history")?;
+        self.entries = read_store(&self.path, "history")?;
+        let out = change(&mut self.entries)?;
+        self.save()?;
+        Ok(out)
+    }
+
+    /// Writes the history atomically via a temporary file.
+    pub fn save(&self) -> Result<()> {
+        write_store(&self.path, "history", &self.entries)
+    }
+}
+
+fn service_record<'a>(
+    entries: &'a mut [HistoryEntry],
+    id: u64,
+    service: &str,
+) -> Result<&'a mut ServiceRecord> {
+    entries
+        .iter_mut()
+        .find(|e| e.id == id)
+        .and_then(|e| e.services.iter_mut().find(|s| s.service == service))
+        .ok_or_else(|| anyhow!("history: no {service} record for #{id}"))
+}
+
+impl HistoryEntry {
+    /// Rebuilds the post that was published, including its images, options and poll.
+    pub fn to_post(&self) -> Result<Post> {
+        rebuild_post(
+            &self.text,
+            &self.options,
+            &self.poll,
+            &self.images,
+            "history",
+        )
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        if self.text.to_lowercase().contains(query) {
+            return true;
+        }
+        self.services
+            .iter()
+            .flat_map(|s| &s.receipts)
+            .any(|r| r.link().to_lowercase().contains(query))
+    }
+}
+
+impl ImageRecord {
+    /// Reads the stored copy back into an attachment.
+    pub fn load(&self) -> Result<Image> {
+        let data =
+            std::fs::read(&self.path).with_context(|| format!("read {}", self.path.display()))?;
+        let mut image = Image::new(data, self.mime_type.clone(), self.alt.clone());
+        image.focus = self.focus;
+        Ok(image)
+    }
+}
+
+/// Reads the JSON list at `path`; a missing file is an empty list. Errors are prefixed
+/// with `label`.
+pub(crate) fn read_store<T: DeserializeOwned>(path: &Path, label: &str) -> Result<Vec<T>> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .with_context(|| format!("{label}: parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("{label}: read {}", path.display())),
+    }
+}
+
+/// Opens (creating it if needed) the lock file at `path`.
+pub(crate) fn open_lock_file(path: &Path, label: &str) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("{label}: create {}", parent.display()))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("{label}: open {}", path.display()))
+}
+
+/// Waits for the exclusive lock on the store at `path` (`<name>.json.lock` next to it).
+/// It is released when the returned file is dropped, or when the process exits.
+pub(crate) fn lock_store(path: &Path, label: &str) -> Result<File> {
+    let lock_path = path.with_extension("json.lock");
+    let file = open_lock_file(&lock_path, label)?;
+    file.lock()
+        .with_context(|| format!("{label}: lock {}", lock_path.display()))?;
+    Ok(file)
+}
+
+/// Writes `entries` to `path` as JSON, atomically via a temporary file.
+pub(crate) fn write_store<T: Serialize>(path: &Path, label: &str, entries: &[T]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("{label}: create {}", parent.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    let bytes =
+        serde_json::to_vec_pretty(entries).with_context(|| format!("{label}: serialize"))?;
+    std::fs::write(&tmp, bytes).with_context(|| format!("{label}: write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("{label}: write {}", path.display()))
+}
+
+/// Rebuilds a stored post, reading its images back from the `media` dir.
+pub(crate) fn rebuild_post(
+    text: &str,
+    options: &PostOptions,
+    poll: &Option<Poll>,
+    images: &[ImageRecord],
+    label: &'static str,
+) -> Result<Post> {
+    let mut post = Post::new(text).with_options(options.clone());
+    post.poll = poll.clone();
+    for image in images {
+        post = post.with_image(image.load().context(label)?);
+    }
+    Ok(post)
+}
+
+/// Copies `images` into the `media` dir next to `store` as `<name>-<index>.<ext>`.
+pub(crate) fn store_media(store: &Path, name: &str, images: &[Image]) -> Result<Vec<ImageRecord>> {
+    if images.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let dir = store
+        .parent()
+        .map(|p| p.join("media"))
+        .unwrap_or_else(|| PathBuf::from("media"));
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let ext = image.mime_type.rsplit('/').next().unwrap_or("bin");
+            let path = dir.join(format!("{name}-{i}.{ext}"));
+            std::fs::write(&path, &image.data)
+                .with_context(|| format!("write {}", path.display()))?;
+            Ok(ImageRecord {
+                path,
+                mime_type: image.mime_type.clone(),
+                alt: image.alt.clone(),
+                focus: image.focus,
+            })
+        })
+        .collect()
+}
+
+impl ServiceRecord {
+    fn from_result(result: &ServiceResult) -> Option<Self> {
+        let status = match &result.outcome {
+            Outcome::Posted => RecordStatus::Posted,
+            Outcome::Failed(e) => RecordStatus::Failed {
+                error: format!("{e:#}"),
+            },
+            Outcome::Skipped(_) => return None,
+        };
+        Some(Self {
+            service: result.key.to_string(),
+            status,
+            receipts: result.receipts.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::services::ConfigError;
+
+    fn tmp_history(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dist-history-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("history.json")
+    }
+
+    fn result(key: &'static str, receipts: Vec<Receipt>, outcome: Outcome) -> ServiceResult {
+        ServiceResult {
+            service: key,
+            key,
+            receipts,
+            outcome,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn record_update_and_rebuild_the_post() {
+        let path = tmp_history("round-trip");
+        let post = Post::new("hello")
+            .with_options(PostOptions {
+                content_warning: Some("cw".into()),
+                ..PostOptions::default()
+            })
+            .with_image(Image::new(vec![7, 8, 9], "image/jpeg", "a photo"));
+
+        let id = History::open(&path)
+            .unwrap()
+            .record(
+                &post,
+                true,
+                &[
+                    result("bsky", Vec::new(), Outcome::Failed(anyhow!("down"))),
+                    result(
+                        "nostr",
+                        Vec::new(),
+                        Outcome::Skipped(ConfigError::Missing("NOSTR_NSEC")),
+                    ),
+                ],
+            )
+            .unwrap()
+            .unwrap();
+
+        let receipt = Receipt::Bluesky {
+            uri: "at://did:plc:x/app.bsky.feed.post/1".into(),
+            cid: "cid".into(),
+        };
+        History::open(&path)
+            .unwrap()
+            .update(id, &result("bsky", vec![receipt.clone()], Outcome::Posted))
+            .unwrap();
+
+        let history = History::open(&path).unwrap();
+        let entry = history.get(id).unwrap();
+        assert!(entry.thread);
+        assert_eq!(entry.services.len(), 1, "skipped services aren't recorded");
+        assert_eq!(entry.services[0].status, RecordStatus::Posted);
+        assert_eq!(entry.services[0].receipts, [receipt]);
+
+        let media = &entry.images[0].path;
+        assert_eq!(
+            media,
+            &path
+                .parent()
+                .unwrap()
+                .join("media")
+                .join(format!("{id}-0.jpeg"))
+        );
+        let rebuilt = entry.to_post().unwrap();
+        assert_eq!(rebuilt.text, "hello");
+        assert_eq!(rebuilt.options.content_warning.as_deref(), Some("cw"));
+        assert_eq!(rebuilt.images[0].data, [7, 8, 9]);
+        assert_eq!(rebuilt.images[0].alt, "a photo");
+    }
+
+    #[test]
+    fn all_skipped_records_nothing() {
+        let path = tmp_history("skipped");
+        let id = History::open(&path)
+            .unwrap()
+            .record(
+                &Post::new("hello"),
+                false,
+                &[result(
+                    "bsky",
+                    Vec::new(),
+                    Outcome::Skipped(ConfigError::Missing("BSKY_HANDLE")),
+                )],
+            )
+            .unwrap();
+        assert_eq!(id, None);
+        assert!(History::open(&path).unwrap().entries().is_empty());
+    }
+}
@@ -0,0 +1,22 @@
+//! Cross-post short updates to Bluesky, Mastodon, and Nostr.
+//!
+//! Build a [`Post`], pick the networks to publish to (either configured clients such as
+//! [`services::bluesky::Bluesky::new`] or everything from [`registry`]), and hand both to
+//! [`publish`] to get one [`ServiceResult`] per network.
+
+pub mod article;
+pub mod config;
+pub mod history;
+pub mod lang;
+pub mod post;
+pub mod queue;
+pub mod services;
+pub mod thread;
+
+pub use article::Article;
+pub use config::Config;
+pub use post::{Focus, Image, Poll, Post, PostOptions, ReplyTo, Visibility};
+pub use services::{
+    ConfigError, ErrorKind, HttpError, Limits, Outcome, PartialDelete, Preview, Receipt, Service,
+    ServicePreview, ServiceResult, preview, publish, publish_thread, registry, resume, unsupported,
+};
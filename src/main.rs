@@ -1,12 +1,21 @@
-mod services;
-
-use crate::services::{bluesky, mastodon, nostr};
-use anyhow::Result;
-use clap::Parser;
-use directories::ProjectDirs;
-use dotenvy::dotenv;
-use futures::join;
-use std::collections::HashMap;
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use dist::history::{History, RecordStatus};
+use dist::queue::{Queue, QueueStatus};
+use dist::services::mastodon::{MIN_SCHEDULE_LEAD, Mastodon};
+use dist::services::nostr::{self, Nostr};
+use dist::{
+    Article, Config, ErrorKind, Focus, Image, Outcome, PartialDelete, Poll, Post, PostOptions,
+    Preview, Receipt, Service, ServicePreview, ServiceResult, Visibility,
+};
+use futures::future::join_all;
+use serde_json::json;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, stdin};
 
 /// Simple, single-binary cross-poster for Bluesky, Mastodon, and Nostr.
@@ -15,12 +24,80 @@ use tokio::io::{AsyncReadExt, stdin};
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Output format
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    #[command(flatten)]
+    post: PostArgs,
+
+    /// Have Mastodon publish the post at this time (RFC 3339); other networks are skipped
+    #[arg(long, value_name = "TIME", conflicts_with = "thread")]
+    at: Option<DateTime<Utc>>,
+
+    /// Print what would be sent to each network without authenticating or posting
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// What to publish and where; shared by posting and `dist queue add`.
+#[derive(clap::Args, Debug)]
+struct PostArgs {
     /// The text to post (ignored when --stdin is provided)
     text: Option<String>,
     /// Read text from STDIN
     #[arg(long)]
     stdin: bool,
 
+    /// Attach an image, or a video on Mastodon and Nostr (repeatable)
+    #[arg(long = "image", value_name = "PATH")]
+    images: Vec<PathBuf>,
+    /// Alt text for the image at the same position (repeatable)
+    #[arg(long = "alt", value_name = "TEXT")]
+    alts: Vec<String>,
+    /// Focal point X,Y (each -1..1) for the image at the same position, used by
+    /// Mastodon to crop previews (repeatable)
+    #[arg(long = "focus", value_name = "X,Y", allow_hyphen_values = true)]
+    focuses: Vec<Focus>,
+
+    /// Add a poll option (repeatable, at least two). Mastodon and Nostr get a native
+    /// poll; Bluesky lists the options in the text
+    #[arg(long = "poll", value_name = "OPTION")]
+    poll_options: Vec<String>,
+    /// How long the poll stays open, e.g. 30m, 12h, 3d
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1d", requires = "poll_options")]
+    poll_expires: u64,
+    /// Let voters pick more than one option
+    #[arg(long, requires = "poll_options")]
+    poll_multiple: bool,
+
+    /// Mastodon visibility: public, unlisted, private (followers only), direct
+    #[arg(long, value_name = "LEVEL")]
+    visibility: Option<Visibility>,
+    /// Content warning shown in place of the text (Mastodon spoiler, Nostr NIP-36)
+    #[arg(long = "cw", value_name = "TEXT")]
+    content_warning: Option<String>,
+    /// Mark the post and its media as sensitive
+    #[arg(long)]
+    sensitive: bool,
+    /// Language of the text as a BCP-47 tag, e.g. en or pt-BR (repeatable, primary first)
+    #[arg(long = "lang", value_name = "TAG", value_parser = parse_lang)]
+    langs: Vec<String>,
+    /// Don't guess the language when --lang is not given
+    #[arg(long)]
+    no_detect_lang: bool,
+
+    /// Split text that exceeds a network's length limit into a numbered reply thread
+    #[arg(long)]
+    thread: bool,
+
+    /// Skip a service by key: bsky, masto, nostr (repeatable)
+    #[arg(long, value_name = "SERVICE")]
+    skip: Vec<String>,
+
     /// Skip a service (useful for testing)
     #[arg(long)]
     no_bsky: bool,
@@ -30,150 +107,1557 @@ struct Args {
     no_nostr: bool,
 }
 
-#[derive(Debug)]
-struct Env {
-    // Bluesky
-    bsky_handle: Option<String>,
-    bsky_password: Option<String>,
-    bsky_pds: String,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List previous cross-posts, newest first
+    History {
+        /// Only show posts whose text or identifiers contain this (case-insensitive)
+        query: Option<String>,
+        /// Maximum number of posts to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Re-attempt only the services that failed for a recorded cross-post
+    Retry {
+        /// Post id as shown by `dist history`
+        id: u64,
+    },
+    /// Delete a recorded cross-post from every network it reached
+    Delete {
+        /// Post id as shown by `dist history`
+        id: u64,
+    },
+    /// Publish Markdown as a Nostr long-form article (NIP-23) and post a teaser linking
+    /// to it on the other networks
+    Article(ArticleArgs),
+    /// Manage posts waiting for `dist run-queue`
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+    /// Publish queued posts that are due; run from cron, or keep running with --loop
+    RunQueue(RunQueueArgs),
+    /// Manage Mastodon statuses scheduled with --at
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+    /// Nostr account maintenance
+    Nostr {
+        #[command(subcommand)]
+        command: NostrCommand,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct ArticleArgs {
+    /// Markdown file to publish; reads STDIN when omitted
+    path: Option<PathBuf>,
+    #[arg(long)]
+    title: String,
+    /// Short description, also used for the teaser
+    #[arg(long)]
+    summary: Option<String>,
+    /// Article identifier (NIP-23 `d` tag); publishing again with the same one replaces
+    /// the article. Defaults to a slug of the title
+    #[arg(long, value_name = "ID")]
+    slug: Option<String>,
+    /// Header image URL
+    #[arg(long, value_name = "URL")]
+    image: Option<String>,
+    /// First publication time (RFC 3339), to keep when republishing an edit
+    #[arg(long, value_name = "TIME")]
+    published_at: Option<DateTime<Utc>>,
+    /// Teaser text for Bluesky and Mastodon (defaults to the title and summary)
+    #[arg(long, value_name = "TEXT")]
+    teaser: Option<String>,
+    /// Print the article event and teasers without publishing
+    #[arg(long)]
+    dry_run: bool,
+    /// Skip a teaser service by key: bsky, masto (repeatable)
+    #[arg(long, value_name = "SERVICE")]
+    skip: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum QueueCommand {
+    /// Queue a post for every network; takes the same options as posting
+    Add {
+        /// When to publish (RFC 3339)
+        #[arg(long, value_name = "TIME")]
+        at: DateTime<Utc>,
+        #[command(flatten)]
+        post: Box<PostArgs>,
+    },
+    /// List queued posts, soonest first
+    List,
+    /// Drop a queued post
+    Remove {
+        /// Queue id as shown by `dist queue list`
+        id: u64,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct RunQueueArgs {
+    /// Keep checking for due posts instead of exiting after one pass
+    #[arg(long = "loop")]
+    keep_running: bool,
+    /// How often to check with --loop
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1m")]
+    interval: u64,
+    /// Posts found more than this long after they were due are marked missed instead
+    /// of published
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1h")]
+    max_late: u64,
+}
 
-    // Mastodon
-    masto_base: Option<String>,
-    masto_token: Option<String>,
+#[derive(Subcommand, Debug)]
+enum ScheduleCommand {
+    /// List statuses the Mastodon server has yet to publish
+    List,
+    /// Cancel a scheduled status before it is published
+    Cancel {
+        /// Post id as shown by `dist schedule list`
+        id: u64,
+    },
+}
 
-    // Nostr
-    nostr_nsec: Option<String>,
-    nostr_relays: Vec<String>,
+#[derive(Subcommand, Debug)]
+enum NostrCommand {
+    /// Manage your NIP-65 relay list
+    Relays {
+        #[command(subcommand)]
+        command: RelaysCommand,
+    },
+    /// Encrypt a private key as a NIP-49 ncryptsec for NOSTR_NSEC
+    ///
+    /// Prompts for the key and a passphrase; without a terminal, reads the key and the
+    /// passphrase as two lines from STDIN.
+    EncryptKey,
 }
 
-impl Env {
-    fn load() -> Self {
-        let config = load_config_defaults();
+#[derive(Subcommand, Debug)]
+enum RelaysCommand {
+    /// Publish NOSTR_RELAYS as your relay list (kind 10002)
+    Sync,
+}
 
-        #[cfg(debug_assertions)]
-        {
-            let _ = dotenv();
-        }
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
 
-        let bsky_pds =
-            lookup_env("BSKY_PDS", &config).unwrap_or_else(|| "https://bsky.social".to_string());
+/// Overall result of a run, reported through the process exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunStatus {
+    /// Every attempted service succeeded (exit 0).
+    Success,
+    /// Every attempted service failed (exit 1).
+    Failure,
+    /// Bad invocation, e.g. no text (exit 2, same as clap's usage errors).
+    Usage,
+    /// Some services succeeded and some failed (exit 3).
+    Partial,
+    /// Nothing could be attempted because of missing or invalid configuration (exit 78).
+    Config,
+}
 
-        let nostr_relays = lookup_env("NOSTR_RELAYS", &config)
-            .map(|s| {
-                s.split(',')
-                    .map(|x| x.trim().to_string())
-                    .filter(|x| !x.is_empty())
-                    .collect()
+impl RunStatus {
+    fn from_results(results: &[ServiceResult]) -> Self {
+        let posted = results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Posted))
+            .count();
+        let failed: Vec<_> = results
+            .iter()
+            .filter_map(|r| match &r.outcome {
+                Outcome::Failed(e) => Some(ErrorKind::of(e)),
+                _ => None,
             })
-            .unwrap_or_else(|| Vec::<String>::new());
+            .collect();
 
-        Self {
-            bsky_handle: lookup_env("BSKY_HANDLE", &config),
-            bsky_password: lookup_env("BSKY_PASSWORD", &config),
-            bsky_pds,
-            masto_base: lookup_env("MASTODON_BASE_URL", &config),
-            masto_token: lookup_env("MASTODON_ACCESS_TOKEN", &config),
-            nostr_nsec: lookup_env("NOSTR_NSEC", &config),
-            nostr_relays,
+        match (posted, failed.len()) {
+            (0, 0) => RunStatus::Config,
+            (_, 0) => RunStatus::Success,
+            (0, _) if failed.iter().all(|k| *k == ErrorKind::Config) => RunStatus::Config,
+            (0, _) => RunStatus::Failure,
+            _ => RunStatus::Partial,
         }
     }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            RunStatus::Success => "success",
+            RunStatus::Failure => "failure",
+            RunStatus::Usage => "usage_error",
+            RunStatus::Partial => "partial",
+            RunStatus::Config => "config_error",
+        }
+    }
+
+    fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            RunStatus::Success => 0,
+            RunStatus::Failure => 1,
+            RunStatus::Usage => 2,
+            RunStatus::Partial => 3,
+            RunStatus::Config => 78,
+        })
+    }
 }
 
-fn load_config_defaults() -> HashMap<String, String> {
-    let mut values = HashMap::new();
+fn parse_lang(tag: &str) -> Result<String, String> {
+    if dist::lang::is_valid_tag(tag) {
+        Ok(tag.to_string())
+    } else {
+        Err(format!(
+            "{tag:?} is not a BCP-47 language tag (e.g. en, pt-BR)"
+        ))
+    }
+}
 
-    if let Some(dirs) = ProjectDirs::from("", "", "dist") {
-        let config_path = dirs.config_dir().join("config.env");
-        if let Ok(iter) = dotenvy::from_path_iter(&config_path) {
-            for item in iter.flatten() {
-                values.insert(item.0, item.1);
+/// Parses a duration such as `90s`, `30m`, `12h` or `3d` into seconds.
+fn parse_duration(value: &str) -> Result<u64, String> {
+    let invalid = || format!("{value:?} is not a duration (e.g. 30m, 12h, 3d)");
+    let trimmed = value.trim();
+    let unit = trimmed.chars().last().ok_or_else(invalid)?;
+    let amount: u64 = trimmed[..trimmed.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    match amount.checked_mul(scale) {
+        Some(seconds) if seconds > 0 => Ok(seconds),
+        _ => Err(invalid()),
+    }
+}
+
+impl Args {
+    /// Parses the command line. Subcommand names win over post text, so posting options
+    /// given next to a subcommand are an error rather than a post.
+    fn parse_from_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let mut command = Self::command();
+        let matches = command.try_get_matches_from_mut(args)?;
+        if let Some(subcommand) = matches.subcommand_name() {
+            let posting = command.get_arguments().find(|arg| {
+                let id = arg.get_id().as_str();
+                !arg.is_global_set() && matches.value_source(id) == Some(ValueSource::CommandLine)
+            });
+            if let Some(arg) = posting {
+                let name = match arg.get_long() {
+                    Some(long) => format!("--{long}"),
+                    None => format!("<{}>", arg.get_id().as_str().to_uppercase()),
+                };
+                return Err(command.error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!(
+                        "{name} is a posting option and can't be used with `dist {subcommand}`"
+                    ),
+                ));
             }
         }
+        Self::from_arg_matches(&matches)
     }
-
-    values
 }
 
-fn lookup_env(key: &str, config: &HashMap<String, String>) -> Option<String> {
-    std::env::var(key).ok().or_else(|| config.get(key).cloned())
+impl PostArgs {
+    fn skips(&self, key: &str) -> bool {
+        let legacy = match key {
+            "bsky" => self.no_bsky,
+            "masto" => self.no_masto,
+            "nostr" => self.no_nostr,
+            _ => false,
+        };
+        legacy || self.skip.iter().any(|s| s.eq_ignore_ascii_case(key))
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    let env = Env::load();
+async fn main() -> ExitCode {
+    let args = match Args::parse_from_args(std::env::args_os()) {
+        Ok(args) => args,
+        // `--help` and `--version` go to stdout as usual.
+        Err(e) if e.use_stderr() && json_requested() => {
+            let message = e.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            return usage_error(message.trim_start_matches("error: "), OutputFormat::Json)
+                .exit_code();
+        }
+        Err(e) => e.exit(),
+    };
+    let output = args.output;
+
+    let result = match &args.command {
+        Some(Command::History { query, limit }) => show_history(query.as_deref(), *limit, output),
+        Some(Command::Retry { id }) => retry(*id, output).await,
+        Some(Command::Delete { id }) => delete(*id, output).await,
+        Some(Command::Article(article_args)) => article(article_args, output).await,
+        Some(Command::Queue {
+            command: QueueCommand::Add { at, post },
+        }) => queue_add(post, *at, output).await,
+        Some(Command::Queue {
+            command: QueueCommand::List,
+        }) => list_queue(output),
+        Some(Command::Queue {
+            command: QueueCommand::Remove { id },
+        }) => remove_queued(*id, output),
+        Some(Command::RunQueue(run_args)) => run_queue(run_args, output).await,
+        Some(Command::Schedule {
+            command: ScheduleCommand::List,
+        }) => list_scheduled(output).await,
+        Some(Command::Schedule {
+            command: ScheduleCommand::Cancel { id },
+        }) => cancel_scheduled(*id, output).await,
+        Some(Command::Nostr {
+            command:
+                NostrCommand::Relays {
+                    command: RelaysCommand::Sync,
+                },
+        }) => sync_relays(output).await,
+        Some(Command::Nostr {
+            command: NostrCommand::EncryptKey,
+        }) => encrypt_key(output),
+        None => post(&args).await,
+    };
+
+    match result {
+        Ok(status) => status.exit_code(),
+        Err(e) => {
+            match output {
+                OutputFormat::Text => eprintln!("Error: {e:?}"),
+                OutputFormat::Json => print_json(&json!({
+                    "status": RunStatus::Failure.as_str(),
+                    "error": format!("{e:#}"),
+                })),
+            }
+            RunStatus::Failure.exit_code()
+        }
+    }
+}
+
+fn show_history(query: Option<&str>, limit: usize, output: OutputFormat) -> Result<RunStatus> {
+    let history = History::open_default()?;
+    let entries = history.search(query.unwrap_or_default()).take(limit);
+    if output == OutputFormat::Json {
+        print_json(&json!({
+            "status": RunStatus::Success.as_str(),
+            "entries": entries.collect::<Vec<_>>(),
+        }));
+        return Ok(RunStatus::Success);
+    }
+
+    for entry in entries {
+        let created = entry.created_at.format("%Y-%m-%d %H:%M:%S UTC");
+        let first_line = entry.text.lines().next().unwrap_or_default();
+        println!("#{}  {created}  {first_line}", entry.id);
+        for record in &entry.services {
+            match &record.status {
+                RecordStatus::Posted => {}
+                RecordStatus::Failed { error } => {
+                    println!("    {:<6} FAILED: {error}", record.service)
+                }
+                RecordStatus::Deleted { deleted_at } => {
+                    let when = deleted_at.format("%Y-%m-%d %H:%M:%S UTC");
+                    println!("    {:<6} deleted {when}", record.service)
+                }
+            }
+            for receipt in &record.receipts {
+                match receipt {
+                    Receipt::MastodonScheduled { id, scheduled_at } => {
+                        let when = scheduled_at.format("%Y-%m-%d %H:%M:%S UTC");
+                        if *scheduled_at <= Utc::now() {
+                            println!(
+                                "    {:<6} published by the server {when} (scheduled as {id})",
+                                record.service
+                            )
+                        } else {
+                            println!("    {:<6} scheduled for {when} ({id})", record.service)
+                        }
+                    }
+                    _ => println!("    {:<6} {}", record.service, receipt.link()),
+                }
+            }
+        }
+    }
+    Ok(RunStatus::Success)
+}
+
+async fn delete(id: u64, output: OutputFormat) -> Result<RunStatus> {
+    let config = Config::load();
+    let registry = dist::registry(&config);
+    let entry = History::open_default()?
+        .get(id)
+        .cloned()
+        .ok_or_else(|| anyhow!("no post #{id} in history"))?;
+
+    let mut deletions = Vec::new();
+    for record in &entry.services {
+        if record.receipts.is_empty() || matches!(record.status, RecordStatus::Deleted { .. }) {
+            continue;
+        }
+        match registry.iter().find(|s| s.key() == record.service) {
+            Some(service) => {
+                let prepared = service.prepare().await;
+                deletions.push(async move {
+                    let result = match prepared {
+                        Ok(()) => service.delete(&record.receipts).await,
+                        Err(e) => Err(e),
+                    };
+                    (service, record, result)
+                })
+            }
+            None => eprintln!("[{}] ERROR: unknown service", record.service),
+        }
+    }
+
+    let results = join_all(deletions).await;
+    // Re-read so posts recorded while the requests ran (e.g. by `run-queue`) are kept.
+    let mut history = History::open_default()?;
+    let mut reports = Vec::new();
+    let (mut deleted, mut failed) = (0, 0);
+    for (service, record, result) in results {
+        let name = service.name();
+        match result {
+            Ok(()) => {
+                deleted += 1;
+                history.mark_deleted(id, &record.service)?;
+                match output {
+                    OutputFormat::Text => println!("[{name}] deleted"),
+                    OutputFormat::Json => reports.push(json!({
+                        "service": name,
+                        "key": service.key(),
+                        "status": "deleted",
+                    })),
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                if let Some(partial) = e.downcast_ref::<PartialDelete>() {
+                    history.remove_receipts(id, &record.service, &partial.deleted)?;
+                }
+                match output {
+                    OutputFormat::Text => eprintln!("[{name}] ERROR: {e:?}"),
+                    OutputFormat::Json => reports.push(json!({
+                        "service": name,
+                        "key": service.key(),
+                        "status": "failed",
+                        "error": error_json(&e),
+                    })),
+                }
+            }
+        }
+    }
+
+    let status = match (deleted, failed) {
+        (_, 0) => RunStatus::Success,
+        (0, _) => RunStatus::Failure,
+        _ => RunStatus::Partial,
+    };
+    if output == OutputFormat::Json {
+        print_json(&json!({ "status": status.as_str(), "id": id, "services": reports }));
+    }
+    Ok(status)
+}
+
+fn encrypt_key(output: OutputFormat) -> Result<RunStatus> {
+    let (secret, passphrase) = if std::io::stdin().is_terminal() {
+        let secret = rpassword::prompt_password("nsec or hex key: ")?;
+        let passphrase = rpassword::prompt_password("Passphrase: ")?;
+        if rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+            return Ok(usage_error("Passphrases do not match.", output));
+        }
+        (secret, passphrase)
+    } else {
+        let mut lines = std::io::stdin().lines();
+        let secret = lines.next().transpose()?.unwrap_or_default();
+        let passphrase = lines.next().transpose()?.unwrap_or_default();
+        (secret, passphrase)
+    };
+    if passphrase.is_empty() {
+        return Ok(usage_error("Passphrase is empty.", output));
+    }
+
+    let ncryptsec = nostr::encrypt_key(secret.trim(), &passphrase)?;
+    match output {
+        OutputFormat::Text => {
+            println!("{ncryptsec}");
+            eprintln!(
+                "Set NOSTR_NSEC to this value; dist asks for the passphrase or runs NOSTR_PASSPHRASE_COMMAND."
+            );
+        }
+        OutputFormat::Json => print_json(&json!({
+            "status": RunStatus::Success.as_str(),
+            "ncryptsec": ncryptsec,
+        })),
+    }
+    Ok(RunStatus::Success)
+}
+
+async fn article(args: &ArticleArgs, output: OutputFormat) -> Result<RunStatus> {
+    let config = Config::load();
+
+    let content = match &args.path {
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|e| anyhow!("read {}: {e}", path.display()))?
+        }
+        None => {
+            let mut buf = String::new();
+            stdin().read_to_string(&mut buf).await?;
+            buf
+        }
+    };
+    if content.trim().is_empty() {
+        return Ok(usage_error("Article is empty.", output));
+    }
 
+    let mut article = Article::new(args.title.trim(), content);
+    if let Some(summary) = &args.summary {
+        article = article.with_summary(summary.trim());
+    }
+    if let Some(slug) = &args.slug {
+        article = article.with_identifier(slug.trim());
+    }
+    if let Some(image) = &args.image {
+        article = article.with_image(image);
+    }
+    if let Some(published_at) = args.published_at {
+        article = article.with_published_at(published_at);
+    }
+
+    let nostr = Nostr::from_config(&config);
+    if let Err(e) = nostr.validate() {
+        match output {
+            OutputFormat::Text => eprintln!("[Nostr] articles need Nostr: {e}"),
+            OutputFormat::Json => print_json(&json!({
+                "status": RunStatus::Config.as_str(),
+                "error": { "kind": ErrorKind::Config, "message": e.to_string() },
+            })),
+        }
+        return Ok(RunStatus::Config);
+    }
+
+    let link = if args.dry_run {
+        nostr.preview_article_link(&article).await?
+    } else {
+        nostr.article_link(&article).await?
+    };
+    let langs = dist::lang::detect(&article.content)
+        .map(String::from)
+        .into_iter()
+        .collect();
+    let teaser =
+        Post::new(article.teaser(args.teaser.as_deref(), &link)).with_options(PostOptions {
+            langs,
+            ..PostOptions::default()
+        });
+    let registry = dist::registry(&config);
+    let services: Vec<_> = registry
+        .iter()
+        .map(Box::as_ref)
+        .filter(|service| {
+            service.key() != nostr.key()
+                && !args
+                    .skip
+                    .iter()
+                    .any(|s| s.eq_ignore_ascii_case(service.key()))
+        })
+        .collect();
+
+    if args.dry_run {
+        let mut previews = vec![ServicePreview {
+            service: nostr.name(),
+            key: nostr.key(),
+            preview: match nostr.render_article(&article).await {
+                Ok(rendered) => Preview::Rendered(vec![rendered]),
+                Err(e) => Preview::Failed(e),
+            },
+        }];
+        previews.extend(dist::preview(&services, &teaser, false).await);
+        return Ok(print_previews(&previews, output));
+    }
+
+    let started = Instant::now();
+    let (receipts, outcome) = match nostr.publish_article(&article).await {
+        Ok(receipt) => (vec![receipt], Outcome::Posted),
+        Err(e) => (Vec::new(), Outcome::Failed(e)),
+    };
+    let mut results = vec![ServiceResult {
+        service: nostr.name(),
+        key: nostr.key(),
+        receipts,
+        outcome,
+        elapsed: started.elapsed(),
+    }];
+    // Only announce the article once it exists; a failed article is not recorded, since
+    // `dist retry` would republish the teaser as a plain note.
+    let published = matches!(results[0].outcome, Outcome::Posted);
+    if published {
+        results.extend(dist::publish(&services, &teaser).await);
+    }
+
+    let status = RunStatus::from_results(&results);
+    let id = if published {
+        match History::open_default().and_then(|mut h| h.record(&teaser, false, &results)) {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("WARNING: could not record history: {e:#}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    match output {
+        OutputFormat::Text => {
+            print_results(&results);
+            if published {
+                println!("Article: {link}");
+            }
+            match id {
+                Some(id)
+                    if results
+                        .iter()
+                        .any(|r| matches!(r.outcome, Outcome::Failed(_))) =>
+                {
+                    println!("Recorded as #{id}; run `dist retry {id}` to retry the teasers")
+                }
+                Some(id) => println!("Recorded as #{id}"),
+                None => {}
+            }
+        }
+        OutputFormat::Json => print_json(&json!({
+            "status": status.as_str(),
+            "id": id,
+            "link": published.then_some(&link),
+            "services": results.iter().map(result_json).collect::<Vec<_>>(),
+        })),
+    }
+    Ok(status)
+}
+
+async fn sync_relays(output: OutputFormat) -> Result<RunStatus> {
+    let nostr = Nostr::from_config(&Config::load());
+    match nostr.publish_relay_list().await {
+        Ok(receipt) => {
+            match output {
+                OutputFormat::Text => {
+                    println!("[Nostr] relay list published: {}", receipt.link());
+                    print_relay_report(&receipt);
+                }
+                OutputFormat::Json => print_json(&json!({
+                    "status": RunStatus::Success.as_str(),
+                    "receipt": receipt,
+                })),
+            }
+            Ok(RunStatus::Success)
+        }
+        Err(e) => Ok(report_error("Nostr", &e, output)),
+    }
+}
+
+async fn list_scheduled(output: OutputFormat) -> Result<RunStatus> {
+    let mastodon = Mastodon::from_config(&Config::load());
+    let scheduled = match mastodon.scheduled().await {
+        Ok(scheduled) => scheduled,
+        Err(e) => return Ok(report_error("Mastodon", &e, output)),
+    };
+    let history = History::open_default()?;
+    let post_id =
+        |status_id: &str| {
+            history
+                .entries()
+                .iter()
+                .find(|entry| {
+                    entry.services.iter().flat_map(|s| &s.receipts).any(
+                        |r| matches!(r, Receipt::MastodonScheduled { id, .. } if id == status_id),
+                    )
+                })
+                .map(|entry| entry.id)
+        };
+
+    match output {
+        OutputFormat::Text => {
+            if scheduled.is_empty() {
+                println!("No scheduled statuses.");
+            }
+            for status in &scheduled {
+                let id = post_id(&status.id).map_or_else(|| "-".to_string(), |id| format!("#{id}"));
+                let when = status.scheduled_at.format("%Y-%m-%d %H:%M:%S UTC");
+                let first_line = status.text.lines().next().unwrap_or_default();
+                println!("{id}  {when}  {first_line}");
+            }
+        }
+        OutputFormat::Json => print_json(&json!({
+            "status": RunStatus::Success.as_str(),
+            "scheduled": scheduled
+                .iter()
+                .map(|status| json!({
+                    "id": post_id(&status.id),
+                    "scheduled_id": status.id,
+                    "scheduled_at": status.scheduled_at,
+                    "text": status.text,
+                }))
+                .collect::<Vec<_>>(),
+        })),
+    }
+    Ok(RunStatus::Success)
+}
+
+/// Cancels a post recorded with a Mastodon scheduled status, like `dist delete`.
+async fn cancel_scheduled(id: u64, output: OutputFormat) -> Result<RunStatus> {
+    let history = History::open_default()?;
+    let entry = history
+        .get(id)
+        .ok_or_else(|| anyhow!("no post #{id} in history"))?;
+    let scheduled = entry
+        .services
+        .iter()
+        .filter(|record| !matches!(record.status, RecordStatus::Deleted { .. }))
+        .flat_map(|record| &record.receipts)
+        .find_map(|receipt| match receipt {
+            Receipt::MastodonScheduled { scheduled_at, .. } => Some(*scheduled_at),
+            _ => None,
+        });
+    match scheduled {
+        None => bail!("post #{id} has no pending scheduled status"),
+        Some(at) if at <= Utc::now() => bail!(
+            "post #{id} was published by the server at {}; delete it with Mastodon",
+            at.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        Some(_) => {}
+    }
+    delete(id, output).await
+}
+
+async fn queue_add(args: &PostArgs, at: DateTime<Utc>, output: OutputFormat) -> Result<RunStatus> {
+    if at <= Utc::now() {
+        return Ok(usage_error("--at must be in the future.", output));
+    }
+    let post = match build_post(args, None).await? {
+        Ok(post) => post,
+        Err(message) => return Ok(usage_error(&message, output)),
+    };
+    let skip = dist::registry(&Config::load())
+        .iter()
+        .map(|service| service.key())
+        .filter(|key| args.skips(key))
+        .map(String::from)
+        .collect();
+
+    let id = Queue::open_default()?.add(&post, args.thread, skip, at)?;
+    match output {
+        OutputFormat::Text => println!(
+            "Queued as #{id} for {}; `dist run-queue` publishes it once due",
+            at.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        OutputFormat::Json => print_json(&json!({
+            "status": RunStatus::Success.as_str(),
+            "id": id,
+            "due_at": at,
+        })),
+    }
+    Ok(RunStatus::Success)
+}
+
+fn list_queue(output: OutputFormat) -> Result<RunStatus> {
+    let queue = Queue::open_default()?;
+    let mut entries = queue.entries().to_vec();
+    entries.sort_by_key(|entry| entry.due_at);
+    if output == OutputFormat::Json {
+        print_json(&json!({
+            "status": RunStatus::Success.as_str(),
+            "entries": entries,
+        }));
+        return Ok(RunStatus::Success);
+    }
+
+    if entries.is_empty() {
+        println!("Queue is empty.");
+    }
+    for entry in &entries {
+        let due = entry.due_at.format("%Y-%m-%d %H:%M:%S UTC");
+        let first_line = entry.text.lines().next().unwrap_or_default();
+        println!("#{}  {due}  {first_line}", entry.id);
+        if !entry.skip.is_empty() {
+            println!("    skips {}", entry.skip.join(", "));
+        }
+        match &entry.status {
+            QueueStatus::Pending => {}
+            QueueStatus::Publishing { claimed_at } => {
+                let when = claimed_at.format("%Y-%m-%d %H:%M:%S UTC");
+                println!("    PUBLISHING since {when}; not retried if the runner stopped")
+            }
+            QueueStatus::Published {
+                published_at,
+                history_id,
+            } => {
+                let when = published_at.format("%Y-%m-%d %H:%M:%S UTC");
+                match history_id {
+                    Some(id) => println!("    published {when} as history #{id}"),
+                    None => println!("    published {when}"),
+                }
+            }
+            QueueStatus::Missed { checked_at } => {
+                let when = checked_at.format("%Y-%m-%d %H:%M:%S UTC");
+                println!("    MISSED: found at {when}, more than --max-late after it was due")
+            }
+        }
+    }
+    Ok(RunStatus::Success)
+}
+
+fn remove_queued(id: u64, output: OutputFormat) -> Result<RunStatus> {
+    Queue::open_default()?.remove(id)?;
+    match output {
+        OutputFormat::Text => println!("Removed #{id} from the queue"),
+        OutputFormat::Json => print_json(&json!({
+            "status": RunStatus::Success.as_str(),
+            "id": id,
+        })),
+    }
+    Ok(RunStatus::Success)
+}
+
+async fn run_queue(args: &RunQueueArgs, output: OutputFormat) -> Result<RunStatus> {
+    // Built once, so a Nostr passphrase is asked for once rather than for every post.
+    let registry = dist::registry(&Config::load());
+    loop {
+        let status = match publish_due(&registry, args, output).await {
+            Ok(status) => status,
+            // A long-running runner reports the failed pass and tries again next time.
+            Err(e) if args.keep_running => report_error("queue", &e, output),
+            Err(e) => return Err(e),
+        };
+        if !args.keep_running {
+            return Ok(status);
+        }
+        tokio::time::sleep(Duration::from_secs(args.interval)).await;
+    }
+}
+
+/// Publishes every queued post that is due, or marks it missed when it is more than
+/// `--max-late` overdue, e.g. because the machine was off.
+async fn publish_due(
+    registry: &[Box<dyn Service>],
+    args: &RunQueueArgs,
+    output: OutputFormat,
+) -> Result<RunStatus> {
+    // Read the due posts only once holding the lock, so they can't already be taken.
+    let Some(_lock) = Queue::lock_default()? else {
+        match output {
+            OutputFormat::Text => {
+                eprintln!("Another `dist run-queue` is publishing; skipping this pass.")
+            }
+            OutputFormat::Json => print_json(&json!({
+                "status": RunStatus::Success.as_str(),
+                "posts": [],
+                "locked": true,
+            })),
+        }
+        return Ok(RunStatus::Success);
+    };
+    let now = Utc::now();
+    let due = Queue::open_default()?.due(now);
+    if due.is_empty() && !args.keep_running {
+        match output {
+            OutputFormat::Text => println!("No queued posts are due."),
+            OutputFormat::Json => print_json(&json!({
+                "status": RunStatus::Success.as_str(),
+                "posts": [],
+            })),
+        }
+        return Ok(RunStatus::Success);
+    }
+
+    let mut statuses = Vec::with_capacity(due.len());
+    let mut reports = Vec::with_capacity(due.len());
+    for entry in due {
+        let due_at = entry.due_at.format("%Y-%m-%d %H:%M:%S UTC");
+        if entry.missed(now, args.max_late) {
+            // Re-open for every update so posts queued meanwhile aren't overwritten.
+            Queue::open_default()?.set_status(entry.id, QueueStatus::Missed { checked_at: now })?;
+            statuses.push(RunStatus::Failure);
+            match output {
+                OutputFormat::Text => {
+                    eprintln!("[queue #{}] MISSED: was due {due_at}", entry.id)
+                }
+                OutputFormat::Json => reports.push(json!({
+                    "queue_id": entry.id,
+                    "status": "missed",
+                    "due_at": entry.due_at,
+                })),
+            }
+            continue;
+        }
+
+        // A post that can't be rebuilt stays pending and is marked missed once late.
+        let post = match entry.to_post() {
+            Ok(post) => post,
+            Err(e) => {
+                statuses.push(RunStatus::Failure);
+                match output {
+                    OutputFormat::Text => eprintln!("[queue #{}] ERROR: {e:?}", entry.id),
+                    OutputFormat::Json => reports.push(json!({
+                        "queue_id": entry.id,
+                        "status": RunStatus::Failure.as_str(),
+                        "error": error_json(&e),
+                    })),
+                }
+                continue;
+            }
+        };
+        let services: Vec<_> = registry
+            .iter()
+            .map(Box::as_ref)
+            .filter(|service| !entry.skips(service.key()))
+            .collect();
+        let (unsupported, services) = partition_unsupported(services, &post);
+        if output == OutputFormat::Text {
+            println!("[queue #{}] publishing (due {due_at})", entry.id);
+            print_unsupported(&unsupported);
+        }
+        // Claim it first: if this pass stops before marking it published, the next one
+        // must not post it again.
+        Queue::open_default()?.claim(entry.id, Utc::now())?;
+        let (results, history_id) = publish_and_record(&services, &post, entry.thread).await;
+        Queue::open_default()?.set_status(
+            entry.id,
+            QueueStatus::Published {
+                published_at: Utc::now(),
+                history_id,
+            },
+        )?;
+
+        let status = RunStatus::from_results(&results);
+        statuses.push(status);
+        match output {
+            OutputFormat::Text => {
+                print_results(&results);
+                print_recorded(history_id, &results);
+            }
+            OutputFormat::Json => reports.push(json!({
+                "queue_id": entry.id,
+                "status": status.as_str(),
+                "id": history_id,
+                "services": unsupported_json(&unsupported)
+                    .chain(results.iter().map(result_json))
+                    .collect::<Vec<_>>(),
+            })),
+        }
+    }
+
+    let status = match statuses.as_slice() {
+        [] => RunStatus::Success,
+        [first, rest @ ..] if rest.iter().all(|s| s == first) => *first,
+        all if !all.contains(&RunStatus::Success) && !all.contains(&RunStatus::Partial) => {
+            RunStatus::Failure
+        }
+        _ => RunStatus::Partial,
+    };
+    if output == OutputFormat::Json && !reports.is_empty() {
+        print_json(&json!({ "status": status.as_str(), "posts": reports }));
+    }
+    Ok(status)
+}
+
+/// Reports a failed single-service command and maps it to an exit status.
+fn report_error(name: &str, e: &anyhow::Error, output: OutputFormat) -> RunStatus {
+    let status = match ErrorKind::of(e) {
+        ErrorKind::Config => RunStatus::Config,
+        _ => RunStatus::Failure,
+    };
+    match output {
+        OutputFormat::Text => eprintln!("[{name}] ERROR: {e:?}"),
+        OutputFormat::Json => print_json(&json!({
+            "status": status.as_str(),
+            "error": error_json(e),
+        })),
+    }
+    status
+}
+
+/// Reads the text and attachments and builds the post, or returns why the invocation is
+/// unusable.
+async fn build_post(
+    args: &PostArgs,
+    scheduled_at: Option<DateTime<Utc>>,
+) -> Result<Result<Post, String>> {
     let text = if args.stdin {
         let mut buf = String::new();
         let mut reader = stdin();
         reader.read_to_string(&mut buf).await?;
         buf.trim().to_string()
     } else {
-        args.text
-            .clone()
-            .unwrap_or_else(|| {
-                eprintln!("Usage: dist \"your text\" (or --stdin)");
-                std::process::exit(1);
-            })
-            .trim()
-            .to_string()
+        match &args.text {
+            Some(text) => text.trim().to_string(),
+            None => {
+                return Ok(Err("Usage: dist \"your text\" (or --stdin)".to_string()));
+            }
+        }
     };
 
     if text.is_empty() {
-        eprintln!("Text is empty.");
-        std::process::exit(1);
+        return Ok(Err("Text is empty.".to_string()));
     }
 
-    let bsky_fut = async {
-        if !args.no_bsky {
-            match (env.bsky_handle.as_deref(), env.bsky_password.as_deref()) {
-                (Some(handle), Some(password)) => {
-                    match bluesky::post_bluesky(&env.bsky_pds, handle, password, &text).await {
-                        Ok(uri) => println!("[Bluesky] OK: {uri}"),
-                        Err(e) => eprintln!("[Bluesky] ERROR: {e:?}"),
-                    }
-                }
-                _ => println!("[Bluesky] skipped (missing env)"),
+    if args.alts.len() > args.images.len() {
+        return Ok(Err(
+            "More --alt values than --image attachments.".to_string()
+        ));
+    }
+    if args.focuses.len() > args.images.len() {
+        return Ok(Err(
+            "More --focus values than --image attachments.".to_string()
+        ));
+    }
+    if args.poll_options.len() == 1 {
+        return Ok(Err("A poll needs at least two --poll options.".to_string()));
+    }
+    let langs = if args.langs.is_empty() && !args.no_detect_lang {
+        dist::lang::detect(&text)
+            .map(String::from)
+            .into_iter()
+            .collect()
+    } else {
+        args.langs.clone()
+    };
+    let mut post = Post::new(text).with_options(PostOptions {
+        visibility: args.visibility,
+        content_warning: args.content_warning.clone(),
+        sensitive: args.sensitive,
+        langs,
+        scheduled_at,
+    });
+    for (i, path) in args.images.iter().enumerate() {
+        let alt = args.alts.get(i).cloned().unwrap_or_default();
+        let mut image = match Image::from_path(path, alt) {
+            Ok(image) => image,
+            Err(e) => {
+                return Ok(Err(format!(
+                    "Cannot attach --image {}: {e:#}",
+                    path.display()
+                )));
             }
-        } else {
-            println!("[Bluesky] skipped (--no-bsky)");
+        };
+        if let Some(focus) = args.focuses.get(i) {
+            image = image.with_focus(*focus);
         }
+        post = post.with_image(image);
+    }
+    if !args.poll_options.is_empty() {
+        post = post.with_poll(
+            Poll::new(args.poll_options.clone(), args.poll_expires)
+                .with_multiple(args.poll_multiple),
+        );
+    }
+    Ok(Ok(post))
+}
+
+async fn post(args: &Args) -> Result<RunStatus> {
+    let config = Config::load();
+
+    if args
+        .at
+        .is_some_and(|at| at - Utc::now() < MIN_SCHEDULE_LEAD)
+    {
+        return Ok(usage_error(
+            "--at must be at least five minutes in the future.",
+            args.output,
+        ));
+    }
+    let post = match build_post(&args.post, args.at).await? {
+        Ok(post) => post,
+        Err(message) => return Ok(usage_error(&message, args.output)),
     };
 
-    let masto_fut = async {
-        if !args.no_masto {
-            match (env.masto_base.as_deref(), env.masto_token.as_deref()) {
-                (Some(base), Some(token)) => {
-                    match mastodon::post_mastodon(base, token, &text).await {
-                        Ok(url) => println!("[Mastodon] OK: {url}"),
-                        Err(e) => eprintln!("[Mastodon] ERROR: {e:?}"),
+    let registry = dist::registry(&config);
+    let (skipped, services): (Vec<_>, Vec<_>) = registry
+        .iter()
+        .map(Box::as_ref)
+        .partition(|service| args.post.skips(service.key()));
+    let (unsupported, services) = partition_unsupported(services, &post);
+    if args.output == OutputFormat::Text {
+        for service in &skipped {
+            println!("[{}] skipped (--skip {})", service.name(), service.key());
+        }
+        print_unsupported(&unsupported);
+    }
+
+    if args.dry_run {
+        return Ok(dry_run(&services, &post, args.post.thread, args.output).await);
+    }
+
+    let (results, id) = publish_and_record(&services, &post, args.post.thread).await;
+    let status = RunStatus::from_results(&results);
+
+    match args.output {
+        OutputFormat::Text => {
+            print_results(&results);
+            print_recorded(id, &results);
+        }
+        OutputFormat::Json => {
+            let mut services: Vec<_> = skipped
+                .iter()
+                .map(|service| {
+                    json!({
+                        "service": service.name(),
+                        "key": service.key(),
+                        "status": "skipped",
+                        "reason": "--skip",
+                    })
+                })
+                .collect();
+            services.extend(unsupported_json(&unsupported));
+            services.extend(results.iter().map(result_json));
+            print_json(&json!({ "status": status.as_str(), "id": id, "services": services }));
+        }
+    }
+
+    Ok(status)
+}
+
+/// A service left out of a post it can't publish as asked, with the reason.
+type Unsupported<'a> = (&'a dyn Service, &'static str);
+
+/// Splits off the services that can't publish `post` as asked (see
+/// [`dist::unsupported`]), with the reason.
+fn partition_unsupported<'a>(
+    services: Vec<&'a dyn Service>,
+    post: &Post,
+) -> (Vec<Unsupported<'a>>, Vec<&'a dyn Service>) {
+    let mut unsupported = Vec::new();
+    let mut supported = Vec::new();
+    for service in services {
+        match dist::unsupported(service, post) {
+            Some(reason) => unsupported.push((service, reason)),
+            None => supported.push(service),
+        }
+    }
+    (unsupported, supported)
+}
+
+fn print_unsupported(unsupported: &[Unsupported]) {
+    for (service, reason) in unsupported {
+        println!("[{}] skipped ({reason})", service.name());
+    }
+}
+
+fn unsupported_json<'a>(
+    unsupported: &'a [Unsupported<'a>],
+) -> impl Iterator<Item = serde_json::Value> + 'a {
+    unsupported.iter().map(|(service, reason)| {
+        json!({
+            "service": service.name(),
+            "key": service.key(),
+            "status": "skipped",
+            "reason": reason,
+        })
+    })
+}
+
+async fn dry_run(
+    services: &[&dyn Service],
+    post: &Post,
+    thread: bool,
+    output: OutputFormat,
+) -> RunStatus {
+    print_previews(&dist::preview(services, post, thread).await, output)
+}
+
+fn print_previews(previews: &[ServicePreview], output: OutputFormat) -> RunStatus {
+    let rendered = previews
+        .iter()
+        .filter(|p| matches!(p.preview, Preview::Rendered(_)))
+        .count();
+    let failed = previews
+        .iter()
+        .filter(|p| matches!(p.preview, Preview::Failed(_)))
+        .count();
+    let status = match (rendered, failed) {
+        (0, 0) => RunStatus::Config,
+        (_, 0) => RunStatus::Success,
+        (0, _) => RunStatus::Failure,
+        _ => RunStatus::Partial,
+    };
+
+    match output {
+        OutputFormat::Text => {
+            for preview in previews {
+                let name = preview.service;
+                match &preview.preview {
+                    Preview::Rendered(parts) => {
+                        for (i, part) in parts.iter().enumerate() {
+                            let pretty = serde_json::to_string_pretty(part).unwrap_or_default();
+                            if parts.len() > 1 {
+                                println!("[{name}] would send part {}/{}:", i + 1, parts.len());
+                            } else {
+                                println!("[{name}] would send:");
+                            }
+                            println!("{pretty}");
+                        }
                     }
+                    Preview::Skipped(e) => println!("[{name}] skipped ({e})"),
+                    Preview::Failed(e) => eprintln!("[{name}] ERROR: {e:?}"),
                 }
-                _ => println!("[Mastodon] skipped (missing env)"),
             }
-        } else {
-            println!("[Mastodon] skipped (--no-masto)");
         }
+        OutputFormat::Json => {
+            let services: Vec<_> = previews
+                .iter()
+                .map(|preview| {
+                    let mut value = json!({ "service": preview.service, "key": preview.key });
+                    match &preview.preview {
+                        Preview::Rendered(parts) => {
+                            value["status"] = json!("rendered");
+                            value["parts"] = json!(parts);
+                        }
+                        Preview::Skipped(e) => {
+                            value["status"] = json!("skipped");
+                            value["reason"] = json!(e.to_string());
+                        }
+                        Preview::Failed(e) => {
+                            value["status"] = json!("failed");
+                            value["error"] = error_json(e);
+                        }
+                    }
+                    value
+                })
+                .collect();
+            print_json(&json!({
+                "status": status.as_str(),
+                "dry_run": true,
+                "services": services,
+            }));
+        }
+    }
+
+    status
+}
+
+/// Publishes `post` and appends the results to the history, whose id is returned.
+async fn publish_and_record(
+    services: &[&dyn Service],
+    post: &Post,
+    thread: bool,
+) -> (Vec<ServiceResult>, Option<u64>) {
+    let results = if thread {
+        dist::publish_thread(services, post).await
+    } else {
+        // The usual limits, so a plain post doesn't wait on a server lookup.
+        for service in services.iter().filter(|s| s.validate().is_ok()) {
+            let limits = service.limits();
+            if let (Some(len), Some(max)) =
+                (limits.exceeded_by(&service.text(post)), limits.max_length)
+            {
+                eprintln!(
+                    "[{}] WARNING: text is {len} characters, over the usual limit of {max}; use --thread to split it",
+                    service.name()
+                );
+            }
+        }
+        dist::publish(services, post).await
     };
 
-    let nostr_fut = async {
-        if !args.no_nostr {
-            match env.nostr_nsec.as_deref() {
-                Some(nsec) => {
-                    let relays = &env.nostr_relays;
-                    match nostr::post_nostr(nsec, relays, &text).await {
-                        Ok(id) => println!("[Nostr] OK: {id}"),
-                        Err(e) => eprintln!("[Nostr] ERROR: {e:?}"),
+    let id = match History::open_default().and_then(|mut h| h.record(post, thread, &results)) {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("WARNING: could not record history: {e:#}");
+            None
+        }
+    };
+    (results, id)
+}
+
+fn print_recorded(id: Option<u64>, results: &[ServiceResult]) {
+    match id {
+        Some(id)
+            if results
+                .iter()
+                .any(|r| matches!(r.outcome, Outcome::Failed(_))) =>
+        {
+            println!("Recorded as #{id}; run `dist retry {id}` to retry failed services")
+        }
+        Some(id) => println!("Recorded as #{id}"),
+        None => {}
+    }
+}
+
+async fn retry(id: u64, output: OutputFormat) -> Result<RunStatus> {
+    let config = Config::load();
+    let registry = dist::registry(&config);
+    let entry = History::open_default()?
+        .get(id)
+        .cloned()
+        .ok_or_else(|| anyhow!("no post #{id} in history"))?;
+    let post = entry.to_post()?;
+    // Mastodon refuses a `scheduled_at` in the past or less than five minutes ahead.
+    if let Some(at) = post.options.scheduled_at
+        && at - Utc::now() < MIN_SCHEDULE_LEAD
+        && entry
+            .services
+            .iter()
+            .any(|record| matches!(record.status, RecordStatus::Failed { .. }))
+    {
+        let message = format!(
+            "#{id} was scheduled for {}, now too soon for Mastodon; post it again with a new --at",
+            at.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        return Ok(usage_error(&message, output));
+    }
+
+    let mut retries = Vec::new();
+    for record in &entry.services {
+        if !matches!(record.status, RecordStatus::Failed { .. }) {
+            continue;
+        }
+        match registry.iter().find(|s| s.key() == record.service) {
+            Some(service) => {
+                let prepared = service.prepare().await;
+                let (post, thread) = (&post, entry.thread);
+                retries.push(async move {
+                    match prepared {
+                        Ok(()) => {
+                            dist::resume(service.as_ref(), post, thread, record.receipts.clone())
+                                .await
+                        }
+                        Err(e) => ServiceResult::failed(service.as_ref(), e),
                     }
+                })
+            }
+            None => eprintln!("[{}] ERROR: unknown service", record.service),
+        }
+    }
+
+    if retries.is_empty() {
+        match output {
+            OutputFormat::Text => println!("Nothing to retry for #{id}"),
+            OutputFormat::Json => print_json(&json!({
+                "status": RunStatus::Success.as_str(),
+                "id": id,
+                "services": [],
+            })),
+        }
+        return Ok(RunStatus::Success);
+    }
+
+    let results = join_all(retries).await;
+    // Re-read so posts recorded while the retries ran (e.g. by `run-queue`) are kept.
+    let mut history = History::open_default()?;
+    for result in &results {
+        history.update(id, result)?;
+    }
+
+    let status = RunStatus::from_results(&results);
+    match output {
+        OutputFormat::Text => print_results(&results),
+        OutputFormat::Json => print_json(&json!({
+            "status": status.as_str(),
+            "id": id,
+            "services": results.iter().map(result_json).collect::<Vec<_>>(),
+        })),
+    }
+    Ok(status)
+}
+
+fn print_results(results: &[ServiceResult]) {
+    for result in results {
+        let name = result.service;
+        for receipt in &result.receipts {
+            match receipt {
+                Receipt::MastodonScheduled { id, scheduled_at } => {
+                    let when = scheduled_at.format("%Y-%m-%d %H:%M:%S UTC");
+                    println!("[{name}] scheduled for {when} ({id})");
+                }
+                _ if !receipt.is_complete() => {
+                    println!("[{name}] incomplete: {}", receipt.link());
                 }
-                None => println!("[Nostr] skipped (missing env)"),
+                _ => println!("[{name}] OK: {}", receipt.link()),
             }
-        } else {
-            println!("[Nostr] skipped (--no-nostr)");
+            print_relay_report(receipt);
         }
-    };
+        match &result.outcome {
+            Outcome::Posted => {}
+            Outcome::Skipped(e) => println!("[{name}] skipped ({e})"),
+            Outcome::Failed(e) => eprintln!("[{name}] ERROR: {e:?}"),
+        }
+    }
+}
 
-    join!(bsky_fut, masto_fut, nostr_fut);
+/// Lists which relays accepted or rejected a Nostr event; other receipts print nothing.
+fn print_relay_report(receipt: &Receipt) {
+    if let Receipt::Nostr {
+        relays, rejected, ..
+    } = receipt
+    {
+        for relay in relays {
+            println!("    accepted by {relay}");
+        }
+        for (relay, reason) in rejected {
+            println!("    rejected by {relay}: {reason}");
+        }
+    }
+}
+
+fn result_json(result: &ServiceResult) -> serde_json::Value {
+    let mut value = json!({
+        "service": result.service,
+        "key": result.key,
+        "receipts": result.receipts,
+        "elapsed_ms": result.elapsed.as_millis() as u64,
+    });
+    match &result.outcome {
+        Outcome::Posted => {
+            value["status"] = json!("posted");
+            value["link"] = json!(result.receipts.first().map(|r| r.link()));
+        }
+        Outcome::Skipped(e) => {
+            value["status"] = json!("skipped");
+            value["reason"] = json!(e.to_string());
+        }
+        Outcome::Failed(e) => {
+            value["status"] = json!("failed");
+            value["error"] = error_json(e);
+        }
+    }
+    value
+}
+
+fn error_json(error: &anyhow::Error) -> serde_json::Value {
+    json!({ "kind": ErrorKind::of(error), "message": format!("{error:#}") })
+}
+
+/// Whether the raw arguments ask for `--output json`, for reporting errors clap finds
+/// before the arguments are parsed.
+fn json_requested() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().any(|arg| arg == "--output=json")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--output" && pair[1] == "json")
+}
+
+/// Reports a bad invocation on stderr, or as the JSON result.
+fn usage_error(message: &str, output: OutputFormat) -> RunStatus {
+    match output {
+        OutputFormat::Text => eprintln!("{message}"),
+        OutputFormat::Json => print_json(&json!({
+            "status": RunStatus::Usage.as_str(),
+            "error": message,
+        })),
+    }
+    RunStatus::Usage
+}
+
+fn print_json(value: &serde_json::Value) {
+    println!("{value}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    #[test]
+    fn subcommands_win_after_global_options() {
+        let args = Args::parse_from_args(["dist", "--output", "json", "history"]).unwrap();
+        assert!(matches!(args.command, Some(Command::History { .. })));
+        assert_eq!(args.output, OutputFormat::Json);
+        assert!(args.post.text.is_none());
+
+        let args = Args::parse_from_args(["dist", "--output", "json", "run-queue"]).unwrap();
+        assert!(matches!(args.command, Some(Command::RunQueue(_))));
+        assert_eq!(args.output, OutputFormat::Json);
+    }
+
+    #[test]
+    fn posting_options_next_to_a_subcommand_are_rejected() {
+        for argv in [
+            &["dist", "--dry-run", "history"][..],
+            &["dist", "--thread", "run-queue"],
+            &["dist", "hello", "history"],
+        ] {
+            let error = Args::parse_from_args(argv).unwrap_err();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn text_without_a_subcommand_is_posted() {
+        let args = Args::parse_from_args(["dist", "--output", "json", "hello there"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.post.text.as_deref(), Some("hello there"));
+    }
+
+    fn result(service: &'static str, outcome: Outcome) -> ServiceResult {
+        ServiceResult {
+            service,
+            key: service,
+            receipts: Vec::new(),
+            outcome,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn missing(key: &'static str) -> anyhow::Error {
+        dist::ConfigError::Missing(key).into()
+    }
+
+    #[test]
+    fn some_posted_and_some_failed_is_partial() {
+        let results = [
+            result("bluesky", Outcome::Posted),
+            result("mastodon", Outcome::Failed(anyhow!("boom"))),
+            result(
+                "nostr",
+                Outcome::Skipped(dist::ConfigError::Missing("NOSTR_NSEC")),
+            ),
+        ];
+        assert_eq!(RunStatus::from_results(&results), RunStatus::Partial);
+    }
+
+    #[test]
+    fn all_failed_on_configuration_is_a_config_error() {
+        let results = [
+            result("bluesky", Outcome::Failed(missing("BSKY_HANDLE"))),
+            result(
+                "nostr",
+                Outcome::Failed(missing("NOSTR_NSEC").context("nostr: sign in")),
+            ),
+        ];
+        let status = RunStatus::from_results(&results);
+        assert_eq!(status, RunStatus::Config);
+        assert_eq!(status.exit_code(), ExitCode::from(78));
+
+        let results = [
+            result("bluesky", Outcome::Failed(missing("BSKY_HANDLE"))),
+            result("mastodon", Outcome::Failed(anyhow!("boom"))),
+        ];
+        assert_eq!(RunStatus::from_results(&results), RunStatus::Failure);
+    }
+
+    #[test]
+    fn all_skipped_is_a_config_error() {
+        let results = [
+            result(
+                "bluesky",
+                Outcome::Skipped(dist::ConfigError::Missing("BSKY_HANDLE")),
+            ),
+            result(
+                "mastodon",
+                Outcome::Skipped(dist::ConfigError::Missing("MASTODON_TOKEN")),
+            ),
+        ];
+        assert_eq!(RunStatus::from_results(&results), RunStatus::Config);
+    }
 }
@@ -0,0 +1,273 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::services::Receipt;
+
+/// Content to cross-post.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Post {
+    pub text: String,
+    pub images: Vec<Image>,
+    /// Thread position when this post continues an earlier one on the same network.
+    pub reply_to: Option<ReplyTo>,
+    pub options: PostOptions,
+    pub poll: Option<Poll>,
+}
+
+impl Post {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            images: Vec::new(),
+            reply_to: None,
+            options: PostOptions::default(),
+            poll: None,
+        }
+    }
+
+    pub fn with_image(mut self, image: Image) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    pub fn with_options(mut self, options: PostOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_poll(mut self, poll: Poll) -> Self {
+        self.poll = Some(poll);
+        self
+    }
+
+    /// The text with the poll options listed after it, for networks without polls.
+    pub fn text_with_poll(&self) -> Cow<'_, str> {
+        match &self.poll {
+            Some(poll) => format!("{}\n\n{}", self.text, poll.to_text()).into(),
+            None => self.text.as_str().into(),
+        }
+    }
+}
+
+/// A poll attached to a post.
+///
+/// Mastodon sends it as a native poll and Nostr as a NIP-88 poll event; Bluesky has
+/// no polls, so the options are listed in the text instead.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Poll {
+    pub options: Vec<String>,
+    /// Seconds the poll stays open.
+    pub expires_in: u64,
+    /// Whether voters may pick more than one option.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub multiple: bool,
+}
+
+impl Poll {
+    pub fn new(options: Vec<String>, expires_in: u64) -> Self {
+        Self {
+            options,
+            expires_in,
+            multiple: false,
+        }
+    }
+
+    pub fn with_multiple(mut self, multiple: bool) -> Self {
+        self.multiple = multiple;
+        self
+    }
+
+    /// The options as a bulleted list, for networks without polls.
+    pub fn to_text(&self) -> String {
+        let bullet = if self.multiple { '☐' } else { '◯' };
+        self.options
+            .iter()
+            .map(|option| format!("{bullet} {option}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Audience, labelling and scheduling settings, mapped onto each network's closest
+/// equivalent.
+///
+/// | Option            | Mastodon       | Bluesky                      | Nostr                    |
+/// |-------------------|----------------|------------------------------|--------------------------|
+/// | `visibility`      | `visibility`   | –                            | –                        |
+/// | `content_warning` | `spoiler_text` | –                            | NIP-36 `content-warning` |
+/// | `sensitive`       | `sensitive`    | `graphic-media` self-label   | NIP-36 `content-warning` |
+/// | `langs`           | `language`     | `langs`                      | NIP-32 `L`/`l` labels    |
+/// | `scheduled_at`    | `scheduled_at` | rejected                     | rejected                 |
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostOptions {
+    /// Falls back to the service's configured default, then [`Visibility::Public`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_warning: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitive: bool,
+    /// BCP-47 language tags, primary language first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub langs: Vec<String>,
+    /// Publish at this time instead of now. Only Mastodon schedules posts; the other
+    /// networks refuse them rather than publishing early.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+impl PostOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Who can see a post, using Mastodon's visibility levels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Unlisted,
+    /// Followers only.
+    Private,
+    /// Mentioned accounts only.
+    Direct,
+}
+
+impl Visibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Unlisted => "unlisted",
+            Self::Private => "private",
+            Self::Direct => "direct",
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "public" => Ok(Self::Public),
+            "unlisted" => Ok(Self::Unlisted),
+            "private" => Ok(Self::Private),
+            "direct" => Ok(Self::Direct),
+            _ => Err(anyhow!(
+                "unknown visibility {s:?} (expected public, unlisted, private, or direct)"
+            )),
+        }
+    }
+}
+
+/// The first and the immediately preceding post of a thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyTo {
+    pub root: Receipt,
+    pub parent: Receipt,
+}
+
+/// An image (or video) attachment with its alt text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub data: Vec<u8>,
+    pub mime_type: String,
+    pub alt: String,
+    /// Point to keep in view when the preview is cropped; only Mastodon uses it.
+    pub focus: Option<Focus>,
+}
+
+impl Image {
+    pub fn new(data: Vec<u8>, mime_type: impl Into<String>, alt: impl Into<String>) -> Self {
+        Self {
+            data,
+            mime_type: mime_type.into(),
+            alt: alt.into(),
+            focus: None,
+        }
+    }
+
+    pub fn with_focus(mut self, focus: Focus) -> Self {
+        self.focus = Some(focus);
+        self
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.mime_type.starts_with("video/")
+    }
+
+    /// Reads an image or video from disk, inferring its MIME type from the file extension.
+    pub fn from_path(path: &Path, alt: impl Into<String>) -> Result<Self> {
+        let mime_type = mime_from_extension(path)
+            .ok_or_else(|| anyhow!("unsupported media type: {}", path.display()))?;
+        let data = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        Ok(Self::new(data, mime_type, alt))
+    }
+}
+
+/// Focal point of an image in Mastodon's coordinates: `x` from -1 (left) to 1 (right),
+/// `y` from -1 (bottom) to 1 (top), `0,0` being the center.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Focus {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl fmt::Display for Focus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+impl FromStr for Focus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (x, y) = s
+            .split_once(',')
+            .ok_or_else(|| anyhow!("focus {s:?} is not X,Y (e.g. 0.5,-0.25)"))?;
+        let coordinate = |v: &str| -> Result<f32> {
+            let v: f32 = v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("focus {s:?} is not X,Y (e.g. 0.5,-0.25)"))?;
+            if !(-1.0..=1.0).contains(&v) {
+                return Err(anyhow!("focus {s:?} is outside -1..1"));
+            }
+            Ok(v)
+        };
+        Ok(Self {
+            x: coordinate(x)?,
+            y: coordinate(y)?,
+        })
+    }
+}
+
+fn mime_from_extension(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "avif" => Some("image/avif"),
+        "mp4" | "m4v" => Some("video/mp4"),
+        "mov" => Some("video/quicktime"),
+        "webm" => Some("video/webm"),
+        _ => None,
+    }
+}
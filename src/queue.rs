@@ -0,0 +1,416 @@
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::project_dirs;
+use crate::history::{
+    ImageRecord, lock_store, open_lock_file, read_store, rebuild_post, store_media, write_store,
+};
+use crate::post::{Poll, Post, PostOptions};
+
+/// Posts waiting for `dist run-queue`, stored as JSON in the user data dir.
+///
+/// Unlike Mastodon's scheduled statuses, queued posts are published by whichever
+/// machine runs the queue, so they work for every network.
+#[derive(Debug)]
+pub struct Queue {
+    path: PathBuf,
+    entries: Vec<QueuedPost>,
+}
+
+/// A post and when to publish it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedPost {
+    pub id: u64,
+    pub added_at: DateTime<Utc>,
+    pub due_at: DateTime<Utc>,
+    pub text: String,
+    /// Whether to publish with `--thread`.
+    #[serde(default)]
+    pub thread: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageRecord>,
+    #[serde(default, skip_serializing_if = "PostOptions::is_empty")]
+    pub options: PostOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<Poll>,
+    /// Keys of the services not to publish to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip: Vec<String>,
+    #[serde(flatten)]
+    pub status: QueueStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum QueueStatus {
+    Pending,
+    /// Claimed by a runner before it calls the networks. An entry left here by a runner
+    /// that stopped before recording the outcome is never published again, since some
+    /// networks may already have it.
+    Publishing {
+        claimed_at: DateTime<Utc>,
+    },
+    /// Handed to the networks; their outcomes are in history entry `history_id`, which
+    /// is `None` when every service was skipped.
+    Published {
+        published_at: DateTime<Utc>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        history_id: Option<u64>,
+    },
+    /// Not published because the runner first saw it too long after it was due.
+    Missed {
+        checked_at: DateTime<Utc>,
+    },
+}
+
+/// Held by `dist run-queue` for a whole pass, so two runners never publish the same
+/// post. The OS releases it when the process exits.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl Queue {
+    /// Opens `queue.json` in the platform data dir.
+    pub fn open_default() -> Result<Self> {
+        Self::open(default_path()?)
+    }
+
+    /// Takes the runner lock for the default queue, or returns `None` when another
+    /// runner holds it.
+    pub fn lock_default() -> Result<Option<RunLock>> {
+        Self::lock(&default_path()?)
+    }
+
+    /// Takes the runner lock for the queue at `path` (`queue.lock` next to it), or
+    /// returns `None` when another runner holds it.
+    pub fn lock(path: &Path) -> Result<Option<RunLock>> {
+        let lock_path = path.with_extension("lock");
+        let file = open_lock_file(&lock_path, "queue")?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(RunLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("queue: lock {}", lock_path.display()))
+            }
+        }
+    }
+
+    /// Opens the queue at `path`; a missing file is an empty queue.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = read_store(&path, "queue")?;
+        Ok(Self { path, entries })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[QueuedPost] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: u64) -> Option<&QueuedPost> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Pending posts due at or before `now`, oldest first.
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<QueuedPost> {
+        let mut due: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|e| e.status == QueueStatus::Pending && e.due_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|e| e.due_at);
+        due
+    }
+
+    /// Queues `post` for `due_at` and writes the queue to disk. Returns the new id.
+    pub fn add(
+        &mut self,
+        post: &Post,
+        thread: bool,
+        skip: Vec<String>,
+        due_at: DateTime<Utc>,
+    ) -> Result<u64> {
+        let path = self.path.clone();
+        self.modify(|entries| {
+            let id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+            let images =
+                store_media(&path, &format!("queue-{id}"), &post.images).context("queue")?;
+            entries.push(QueuedPost {
+                id,
+                added_at: Utc::now(),
+                due_at,
+                text: post.text.clone(),
+                thread,
+                images,
+                options: post.options.clone(),
+                poll: post.poll.clone(),
+                skip,
+                status: QueueStatus::Pending,
+            });
+            Ok(id)
+        })
+    }
+
+    /// Marks pending entry `id` as [`QueueStatus::Publishing`] and writes the queue to
+    /// disk. Call it before publishing, so a pass that stops midway can't post it twice.
+    pub fn claim(&mut self, id: u64, claimed_at: DateTime<Utc>) -> Result<()> {
+        self.modify(|entries| {
+            let entry = entry_mut(entries, id)?;
+            if entry.status != QueueStatus::Pending {
+                bail!("queue: #{id} is not pending");
+            }
+            entry.status = QueueStatus::Publishing { claimed_at };
+            Ok(())
+        })
+    }
+
+    /// Sets the status of entry `id` and writes the queue to disk. A claimed entry can't
+    /// go back to pending.
+    ///
+    /// Published entries no longer need their media copies, since the history keeps its
+    /// own, so those files are removed.
+    pub fn set_status(&mut self, id: u64, status: QueueStatus) -> Result<()> {
+        self.modify(|entries| {
+            let entry = entry_mut(entries, id)?;
+            if matches!(entry.status, QueueStatus::Publishing { .. })
+                && status == QueueStatus::Pending
+            {
+                bail!("queue: #{id} was already claimed for publishing");
+            }
+            if matches!(status, QueueStatus::Published { .. }) {
+                remove_media(&entry.images);
+                entry.images.clear();
+            }
+            entry.status = status;
+            Ok(())
+        })
+    }
+
+    /// Drops entry `id` and its media, and writes the queue to disk.
+    pub fn remove(&mut self, id: u64) -> Result<QueuedPost> {
+        self.modify(|entries| {
+            let index = entries
+                .iter()
+                .position(|e| e.id == id)
+                .ok_or_else(|| anyhow!("queue: no entry #{id}"))?;
+            let entry = entries.remove(index);
+            remove_media(&entry.images);
+            Ok(entry)
+        })
+    }
+
+    /// Applies `change` to the entries on disk and writes them back, holding the store
+    /// lock (not the runner lock) so `queue add` can't lose a runner's status update.
+    fn modify<R>(&mut self, change: impl FnOnce(&mut Vec<QueuedPost>) -> Result<R>) -> Result<R> {
+        let _lock = lock_store(&self.path, "queue")?;
+        self.entries = read_store(&self.path, "queue")?;
+        let out = change(&mut self.entries)?;
+        self.save()?;
+        Ok(out)
+    }
+
+    /// Writes the queue atomically via a temporary file.
+    pub fn save(&self) -> Result<()> {
+        write_store(&self.path, "queue", &self.entries)
+    }
+}
+
+impl QueuedPost {
+    /// Rebuilds the post to publish, including its images, options and poll.
+    pub fn to_post(&self) -> Result<Post> {
+        rebuild_post(&self.text, &self.options, &self.poll, &self.images, "queue")
+    }
+
+    /// Whether the post is more than `max_late` seconds overdue at `now`, too late to
+    /// publish.
+    pub fn missed(&self, now: DateTime<Utc>, max_late: u64) -> bool {
+        i64::try_from(max_late).is_ok_and(|max_late| (now - self.due_at).num_seconds() > max_late)
+    }
+
+    /// Whether the service with `key` should be left out.
+    pub fn skips(&self, key: &str) -> bool {
+        self.skip.iter().any(|s| s.eq_ignore_ascii_case(key))
+    }
+}
+
+fn entry_mut(entries: &mut [QueuedPost], id: u64) -> Result<&mut QueuedPost> {
+    entries
+        .iter_mut()
+        .find(|e| e.id == id)
+        .ok_or_else(|| anyhow!("queue: no entry #{id}"))
+}
+
+fn default_path() -> Result<PathBuf> {
+    let dirs = project_dirs().ok_or_else(|| anyhow!("queue: no home directory"))?;
+    Ok(dirs.data_dir().join("queue.json"))
+}
+
+/// Best-effort cleanup; a leftover file only costs disk space.
+fn remove_media(images: &[ImageRecord]) {
+    for image in images {
+        let _ = std::fs::remove_file(&image.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::post::Image;
+
+    fn tmp_queue(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dist-queue-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("queue.json")
+    }
+
+    #[test]
+    fn due_returns_pending_posts_oldest_first() {
+        let path = tmp_queue("due");
+        let now = Utc::now();
+        let mut queue = Queue::open(&path).unwrap();
+        let later = queue
+            .add(
+                &Post::new("later"),
+                false,
+                Vec::new(),
+                now + TimeDelta::hours(1),
+            )
+            .unwrap();
+        let second = queue
+            .add(
+                &Post::new("second"),
+                false,
+                Vec::new(),
+                now - TimeDelta::minutes(1),
+            )
+            .unwrap();
+        let first = queue
+            .add(
+                &Post::new("first"),
+                true,
+                vec!["nostr".into()],
+                now - TimeDelta::minutes(5),
+            )
+            .unwrap();
+
+        let queue = Queue::open(&path).unwrap();
+        let due: Vec<_> = queue.due(now).iter().map(|e| e.id).collect();
+        assert_eq!(due, [first, second]);
+        assert!(!due.contains(&later));
+        let entry = queue.get(first).unwrap();
+        assert!(entry.thread);
+        assert!(entry.skips("NOSTR"));
+        assert!(!entry.skips("bsky"));
+    }
+
+    #[test]
+    fn set_status_takes_posts_out_of_due_and_drops_published_media() {
+        let path = tmp_queue("status");
+        let now = Utc::now();
+        let post =
+            Post::new("with image").with_image(Image::new(vec![1, 2, 3], "image/png", "alt"));
+        let mut queue = Queue::open(&path).unwrap();
+        let id = queue.add(&post, false, Vec::new(), now).unwrap();
+        let media = queue.get(id).unwrap().images[0].path.clone();
+        assert!(media.exists());
+        assert_eq!(
+            queue.due(now)[0].to_post().unwrap().images[0].data,
+            [1, 2, 3]
+        );
+
+        let published = QueueStatus::Published {
+            published_at: now,
+            history_id: Some(4),
+        };
+        queue.set_status(id, published.clone()).unwrap();
+
+        let queue = Queue::open(&path).unwrap();
+        assert!(queue.due(now).is_empty());
+        assert_eq!(queue.get(id).unwrap().status, published);
+        assert!(queue.get(id).unwrap().images.is_empty());
+        assert!(!media.exists());
+    }
+
+    #[test]
+    fn posts_overdue_by_more_than_max_late_are_missed() {
+        let path = tmp_queue("missed");
+        let now = Utc::now();
+        let mut queue = Queue::open(&path).unwrap();
+        let late = queue
+            .add(
+                &Post::new("late"),
+                false,
+                Vec::new(),
+                now - TimeDelta::hours(2),
+            )
+            .unwrap();
+        let recent = queue
+            .add(
+                &Post::new("recent"),
+                false,
+                Vec::new(),
+                now - TimeDelta::minutes(30),
+            )
+            .unwrap();
+
+        let max_late = 60 * 60;
+        for entry in queue.due(now) {
+            if entry.missed(now, max_late) {
+                queue
+                    .set_status(entry.id, QueueStatus::Missed { checked_at: now })
+                    .unwrap();
+            }
+        }
+
+        let queue = Queue::open(&path).unwrap();
+        assert_eq!(
+            queue.get(late).unwrap().status,
+            QueueStatus::Missed { checked_at: now }
+        );
+        let due: Vec<_> = queue.due(now).iter().map(|e| e.id).collect();
+        assert_eq!(due, [recent]);
+        assert!(!queue.get(recent).unwrap().missed(now, max_late));
+    }
+
+    #[test]
+    fn claimed_entry_is_not_due_again_after_a_failed_pass() {
+        let path = tmp_queue("claim");
+        let now = Utc::now();
+        let id = Queue::open(&path)
+            .unwrap()
+            .add(&Post::new("hello"), false, Vec::new(), now)
+            .unwrap();
+
+        // A pass that claims the entry and then fails before recording the outcome.
+        let publish = |_: &QueuedPost| -> Result<()> { bail!("killed while publishing") };
+        let pass = || -> Result<()> {
+            let mut queue = Queue::open(&path)?;
+            for entry in queue.due(now) {
+                queue.claim(entry.id, now)?;
+                publish(&entry)?;
+            }
+            Ok(())
+        };
+        assert!(pass().is_err());
+
+        let mut queue = Queue::open(&path).unwrap();
+        assert!(queue.due(now).is_empty());
+        assert!(queue.claim(id, now).is_err());
+        assert!(queue.set_status(id, QueueStatus::Pending).is_err());
+        assert_eq!(
+            queue.get(id).unwrap().status,
+            QueueStatus::Publishing { claimed_at: now }
+        );
+    }
+}
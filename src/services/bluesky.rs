@@ -1,64 +1,187 @@
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use futures::{
+    FutureExt,
+    future::{BoxFuture, join_all},
+};
+use hickory_resolver::{TokioResolver, proto::rr::RData};
 use html_escape::decode_html_entities;
-use linkify::{LinkFinder, LinkKind};
 use reqwest::{
     Url,
     header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
 };
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use super::detect::{DetectedLink, DetectedTag, detect_hashtags, detect_links, starts_word};
+use super::{ConfigError, HttpError, Limits, Receipt, Service};
+use crate::config::{Config, project_dirs, write_private};
+use crate::post::{Image, Post, ReplyTo};
+use crate::thread::grapheme_len;
 
 const PREVIEW_MAX_BYTES: usize = 64 * 1024;
 const THUMB_MAX_BYTES: usize = 1_500_000;
+const IMAGE_MAX_BYTES: usize = 1_000_000;
+const BSKY_MAX_IMAGES: usize = 4;
+const BSKY_MAX_LANGS: usize = 3;
 const BSKY_EMBED_TEXT_LIMIT: usize = 300;
+const BSKY_POST_GRAPHEME_LIMIT: usize = 300;
+const JWT_EXPIRY_LEEWAY_SECS: i64 = 60;
+const PLC_DIRECTORY: &str = "https://plc.directory";
+
+pub struct Bluesky {
+    handle: Option<String>,
+    password: Option<String>,
+    /// `None` discovers the PDS from the handle's DID document.
+    pds: Option<String>,
+}
 
-pub async fn post_bluesky(pds: &str, handle: &str, password: &str, text: &str) -> Result<String> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()
-        .context("bsky: build http client")?;
+impl Bluesky {
+    pub fn new(
+        pds: impl Into<String>,
+        handle: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        Self {
+            handle: Some(handle.into()),
+            password: Some(password.into()),
+            pds: Some(pds.into()),
+        }
+    }
 
-    let sess_resp = client
-        .post(format!(
-            "{}/xrpc/com.atproto.server.createSession",
-            pds.trim_end_matches('/')
-        ))
-        .json(&serde_json::json!({ "identifier": handle, "password": password }))
-        .send()
-        .await
-        .context("bsky: createSession request failed")?;
+    /// A client whose PDS is discovered from the handle's DID document.
+    pub fn discover(handle: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            handle: Some(handle.into()),
+            password: Some(password.into()),
+            pds: None,
+        }
+    }
 
-    if !sess_resp.status().is_success() {
-        return Err(anyhow!("bsky: createSession status={}", sess_resp.status()));
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            handle: config.get("BSKY_HANDLE"),
+            password: config.get("BSKY_PASSWORD"),
+            pds: config.get("BSKY_PDS"),
+        }
     }
-    let session: BskySession = sess_resp.json().await.context("bsky: parse session")?;
 
-    let links = detect_links(text);
-    let preview = match links.first() {
-        Some(first) => fetch_link_preview(&client, &first.url).await,
-        None => None,
-    };
-    let thumb = if let (Some(first), Some(preview)) = (links.first(), preview.as_ref()) {
-        if let Some(image_url) = preview.image.as_ref() {
-            fetch_thumbnail_blob(&client, &first.url, image_url, pds, &session.access_jwt).await
-        } else {
-            None
+    /// The configured PDS, else the one cached with the session, else the one the
+    /// handle's DID document names.
+    ///
+    /// A failed discovery is a config error rather than a guess, so the app password is
+    /// never sent to a PDS that doesn't host the account.
+    async fn pds(&self, handle: &str) -> Result<String> {
+        if let Some(pds) = &self.pds {
+            return Ok(pds.clone());
         }
-    } else {
-        None
-    };
-    let facets = build_bsky_facets(&links);
-    let embed_preview = preview.clone();
-    let record = BskyPostRecord {
-        typ: "app.bsky.feed.post",
-        text,
-        created_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
-        langs: None,
-        facets,
-        embed: build_bsky_external_embed(links.first(), embed_preview, thumb),
-    };
+        let cached = session_cache_path()
+            .as_deref()
+            .and_then(read_session_cache)
+            .filter(|cached| cached.handle.eq_ignore_ascii_case(handle));
+        if let Some(cached) = cached {
+            return Ok(cached.pds);
+        }
+        discover_pds(&http_client()?, handle).await.map_err(|e| {
+            ConfigError::Invalid {
+                key: "BSKY_HANDLE",
+                reason: format!(
+                    "could not discover the PDS for {handle} ({e:#}); set BSKY_PDS to its URL"
+                ),
+            }
+            .into()
+        })
+    }
+}
+
+impl Service for Bluesky {
+    fn name(&self) -> &'static str {
+        "Bluesky"
+    }
+
+    fn key(&self) -> &'static str {
+        "bsky"
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            max_length: Some(BSKY_POST_GRAPHEME_LIMIT),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.handle.is_none() {
+            return Err(ConfigError::Missing("BSKY_HANDLE"));
+        }
+        if self.password.is_none() {
+            return Err(ConfigError::Missing("BSKY_PASSWORD"));
+        }
+        Ok(())
+    }
+
+    fn supports_polls(&self) -> bool {
+        false
+    }
+
+    fn post<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<Receipt>> {
+        async move {
+            self.validate()?;
+            let handle = self.handle.as_deref().unwrap_or_default();
+            let password = self.password.as_deref().unwrap_or_default();
+            let pds = self.pds(handle).await?;
+            post_bluesky(&pds, handle, password, post).await
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, receipts: &'a [Receipt]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.validate()?;
+            let handle = self.handle.as_deref().unwrap_or_default();
+            let password = self.password.as_deref().unwrap_or_default();
+            let pds = self.pds(handle).await?;
+            delete_bluesky(&pds, handle, password, receipts).await
+        }
+        .boxed()
+    }
+
+    fn dry_run<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<serde_json::Value>> {
+        async move {
+            let pds = self.pds(self.handle.as_deref().unwrap_or_default()).await?;
+            render_bluesky(&pds, post).await
+        }
+        .boxed()
+    }
+}
+
+pub async fn post_bluesky(pds: &str, handle: &str, password: &str, post: &Post) -> Result<Receipt> {
+    if post.options.scheduled_at.is_some() {
+        return Err(anyhow!("bsky: scheduled posts are not supported"));
+    }
+    let client = http_client()?;
+    let session = session(&client, pds, handle, password).await?;
+    match create_post(&client, pds, &session, post).await {
+        Err(e) if e.is::<SessionRejected>() => {
+            let session = renew_session(&client, pds, handle, password, &session).await?;
+            create_post(&client, pds, &session, post).await
+        }
+        result => result,
+    }
+}
+
+async fn create_post(
+    client: &reqwest::Client,
+    pds: &str,
+    session: &BskySession,
+    post: &Post,
+) -> Result<Receipt> {
+    let text = post.text_with_poll();
+    let record = build_record(client, pds, post, &text, Blobs::Upload(session)).await?;
     let payload = BskyCreateRecordReq {
         repo: &session.did,
         collection: "app.bsky.feed.post",
@@ -77,15 +200,522 @@ pub async fn post_bluesky(pds: &str, handle: &str, password: &str, text: &str) -
         .context("bsky: createRecord request failed")?;
 
     if !rec_resp.status().is_success() {
-        return Err(anyhow!("bsky: createRecord status={}", rec_resp.status()));
+        return Err(response_error("bsky: createRecord", rec_resp).await);
     }
     let out: BskyCreateRecordResp = rec_resp.json().await.context("bsky: parse createRecord")?;
-    Ok(out.uri)
+    Ok(Receipt::Bluesky {
+        uri: out.uri,
+        cid: out.cid,
+    })
+}
+
+/// Builds the record `post_bluesky` would create, without authenticating or uploading.
+///
+/// Mentions are still resolved against `pds`, since that lookup needs no session.
+///
+/// Blobs that would be uploaded (link-card thumbnail, images) are described in place of
+/// their blob refs.
+pub async fn render_bluesky(pds: &str, post: &Post) -> Result<serde_json::Value> {
+    if post.options.scheduled_at.is_some() {
+        return Err(anyhow!("bsky: scheduled posts are not supported"));
+    }
+    let text = post.text_with_poll();
+    let client = http_client()?;
+    let record = build_record(&client, pds, post, &text, Blobs::Placeholder).await?;
+
+    Ok(json!({
+        "pds": pds,
+        "graphemes": grapheme_len(&text),
+        "limit": BSKY_POST_GRAPHEME_LIMIT,
+        "record": record,
+    }))
+}
+
+/// Where [`build_record`] gets the blobs for images and the link-card thumbnail.
+enum Blobs<'a> {
+    /// Uploads them to the PDS with the session's token.
+    Upload(&'a BskySession),
+    /// Describes what would be uploaded instead, for dry runs.
+    Placeholder,
+}
+
+impl Blobs<'_> {
+    async fn image(
+        &self,
+        client: &reqwest::Client,
+        pds: &str,
+        image: &Image,
+    ) -> Result<BskyUpload> {
+        if image.data.len() > IMAGE_MAX_BYTES {
+            return Err(anyhow!(
+                "bsky: image is {} bytes (limit {IMAGE_MAX_BYTES})",
+                image.data.len()
+            ));
+        }
+        match self {
+            Blobs::Upload(session) => upload_blob(
+                client,
+                pds,
+                &session.access_jwt,
+                image.data.clone(),
+                &image.mime_type,
+            )
+            .await
+            .map(BskyUpload::Blob),
+            Blobs::Placeholder => Ok(BskyUpload::Pending {
+                upload: image.mime_type.clone(),
+                size: Some(image.data.len()),
+            }),
+        }
+    }
+
+    async fn thumbnail(
+        &self,
+        client: &reqwest::Client,
+        pds: &str,
+        page_url: &str,
+        image_url: &str,
+    ) -> Option<BskyUpload> {
+        match self {
+            Blobs::Upload(session) => {
+                fetch_thumbnail_blob(client, page_url, image_url, pds, &session.access_jwt)
+                    .await
+                    .map(BskyUpload::Blob)
+            }
+            Blobs::Placeholder => resolve_url(page_url, image_url).map(|url| BskyUpload::Pending {
+                upload: url.to_string(),
+                size: None,
+            }),
+        }
+    }
+}
+
+/// Builds the `app.bsky.feed.post` record for `post`, whose text (poll included) is
+/// `text`: facets, reply reference, and an image or link-card embed.
+async fn build_record<'a>(
+    client: &reqwest::Client,
+    pds: &str,
+    post: &'a Post,
+    text: &'a str,
+    blobs: Blobs<'_>,
+) -> Result<BskyPostRecord<'a>> {
+    let reply = post.reply_to.as_ref().map(build_reply_ref).transpose()?;
+    check_images(&post.images)?;
+    let links = detect_links(text);
+    let mentions = resolve_mentions(client, pds, detect_mentions(text)).await;
+    let tags = detect_hashtags(text, &links);
+    let facets = build_bsky_facets(&links, &mentions, &tags);
+    let embed = if post.images.is_empty() {
+        let preview = match links.first() {
+            Some(first) => fetch_link_preview(client, &first.url).await,
+            None => None,
+        };
+        let image_url = preview
+            .as_ref()
+            .and_then(|preview| preview.image.as_deref());
+        let thumb = match (links.first(), image_url) {
+            (Some(first), Some(image_url)) => {
+                blobs.thumbnail(client, pds, &first.url, image_url).await
+            }
+            _ => None,
+        };
+        build_bsky_external_embed(links.first(), preview, thumb).map(BskyEmbed::External)
+    } else {
+        let mut images = Vec::with_capacity(post.images.len());
+        for image in &post.images {
+            images.push(BskyImage {
+                image: blobs.image(client, pds, image).await?,
+                alt: image.alt.clone(),
+            });
+        }
+        Some(BskyEmbed::Images(BskyImagesEmbed {
+            typ: "app.bsky.embed.images",
+            images,
+        }))
+    };
+    Ok(BskyPostRecord {
+        typ: "app.bsky.feed.post",
+        text,
+        created_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+        langs: build_langs(post),
+        labels: build_self_labels(post),
+        reply,
+        facets,
+        embed,
+    })
+}
+
+/// Deletes the `app.bsky.feed.post` records behind `receipts`.
+pub async fn delete_bluesky(
+    pds: &str,
+    handle: &str,
+    password: &str,
+    receipts: &[Receipt],
+) -> Result<()> {
+    let client = http_client()?;
+    let session = session(&client, pds, handle, password).await?;
+    match delete_records(&client, pds, &session, receipts).await {
+        Err(e) if e.is::<SessionRejected>() => {
+            let session = renew_session(&client, pds, handle, password, &session).await?;
+            delete_records(&client, pds, &session, receipts).await
+        }
+        result => result,
+    }
+}
+
+/// Deleting a record that is already gone succeeds, so a retry can start over.
+async fn delete_records(
+    client: &reqwest::Client,
+    pds: &str,
+    session: &BskySession,
+    receipts: &[Receipt],
+) -> Result<()> {
+    for receipt in receipts {
+        let Receipt::Bluesky { uri, .. } = receipt else {
+            return Err(anyhow!("bsky: cannot delete {receipt:?}"));
+        };
+        let (repo, collection, rkey) = parse_at_uri(uri)?;
+        let resp = client
+            .post(format!(
+                "{}/xrpc/com.atproto.repo.deleteRecord",
+                pds.trim_end_matches('/')
+            ))
+            .header(AUTHORIZATION, format!("Bearer {}", session.access_jwt))
+            .json(&serde_json::json!({
+                "repo": repo,
+                "collection": collection,
+                "rkey": rkey,
+            }))
+            .send()
+            .await
+            .context("bsky: deleteRecord request failed")?;
+
+        if !resp.status().is_success() {
+            return Err(response_error("bsky: deleteRecord", resp).await);
+        }
+    }
+
+    Ok(())
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .context("bsky: build http client")
+}
+
+/// Returns a session for `handle`, reusing the one cached in the config dir when possible.
+///
+/// An expired access token is renewed with `refreshSession`; `createSession`, which
+/// Bluesky rate-limits tightly, only runs when there is no cached session or the refresh
+/// token is no longer accepted. A token the server refuses before it expires is replaced
+/// by [`renew_session`].
+async fn session(
+    client: &reqwest::Client,
+    pds: &str,
+    handle: &str,
+    password: &str,
+) -> Result<BskySession> {
+    let cache = session_cache_path();
+    let cached = cache
+        .as_deref()
+        .and_then(|path| load_cached_session(path, pds, handle));
+
+    if let Some(cached) = cached {
+        if !jwt_expired(&cached.access_jwt) {
+            return Ok(cached);
+        }
+        if !jwt_expired(&cached.refresh_jwt)
+            && let Ok(session) = refresh_session(client, pds, &cached.refresh_jwt).await
+        {
+            if let Some(path) = cache.as_deref() {
+                let _ = store_session(path, pds, handle, &session);
+            }
+            return Ok(session);
+        }
+    }
+
+    let session = create_session(client, pds, handle, password).await?;
+    if let Some(path) = cache.as_deref() {
+        let _ = store_session(path, pds, handle, &session);
+    }
+    Ok(session)
+}
+
+/// Replaces a session whose access token the server refused, e.g. after it was revoked,
+/// the password changed, or the clocks disagree about its expiry.
+///
+/// The cache is dropped first so a failure here doesn't leave the refused session to be
+/// reused; then `refreshSession` is tried once before falling back to `createSession`.
+async fn renew_session(
+    client: &reqwest::Client,
+    pds: &str,
+    handle: &str,
+    password: &str,
+    rejected: &BskySession,
+) -> Result<BskySession> {
+    let cache = session_cache_path();
+    if let Some(path) = cache.as_deref() {
+        let _ = std::fs::remove_file(path);
+    }
+    let session = match refresh_session(client, pds, &rejected.refresh_jwt).await {
+        Ok(session) => session,
+        Err(_) => create_session(client, pds, handle, password).await?,
+    };
+    if let Some(path) = cache.as_deref() {
+        let _ = store_session(path, pds, handle, &session);
+    }
+    Ok(session)
+}
+
+/// The PDS refused the session's access token.
+#[derive(Debug, Error)]
+#[error("bsky: session rejected")]
+struct SessionRejected;
+
+/// The error for a failed authenticated request, marked [`SessionRejected`] when the
+/// status is 401 or a 400 with an `ExpiredToken`/`InvalidToken` XRPC error.
+async fn response_error(context: &'static str, resp: reqwest::Response) -> anyhow::Error {
+    let status = resp.status();
+    let error = anyhow::Error::new(HttpError::new(context, status));
+    let rejected = status == reqwest::StatusCode::UNAUTHORIZED
+        || (status == reqwest::StatusCode::BAD_REQUEST
+            && resp.json::<XrpcError>().await.is_ok_and(|body| {
+                matches!(body.error.as_deref(), Some("ExpiredToken" | "InvalidToken"))
+            }));
+    if rejected {
+        error.context(SessionRejected)
+    } else {
+        error
+    }
+}
+
+async fn create_session(
+    client: &reqwest::Client,
+    pds: &str,
+    handle: &str,
+    password: &str,
+) -> Result<BskySession> {
+    let sess_resp = client
+        .post(format!(
+            "{}/xrpc/com.atproto.server.createSession",
+            pds.trim_end_matches('/')
+        ))
+        .json(&serde_json::json!({ "identifier": handle, "password": password }))
+        .send()
+        .await
+        .context("bsky: createSession request failed")?;
+
+    if !sess_resp.status().is_success() {
+        return Err(HttpError::new("bsky: createSession", sess_resp.status()).into());
+    }
+    sess_resp.json().await.context("bsky: parse session")
+}
+
+async fn refresh_session(
+    client: &reqwest::Client,
+    pds: &str,
+    refresh_jwt: &str,
+) -> Result<BskySession> {
+    let resp = client
+        .post(format!(
+            "{}/xrpc/com.atproto.server.refreshSession",
+            pds.trim_end_matches('/')
+        ))
+        .header(AUTHORIZATION, format!("Bearer {refresh_jwt}"))
+        .send()
+        .await
+        .context("bsky: refreshSession request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("bsky: refreshSession", resp.status()).into());
+    }
+    resp.json().await.context("bsky: parse session")
+}
+
+fn session_cache_path() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().join("bsky-session.json"))
+}
+
+/// Reads the cached session, ignoring it when it belongs to another account or PDS.
+fn load_cached_session(path: &Path, pds: &str, handle: &str) -> Option<BskySession> {
+    let cached = read_session_cache(path)?;
+    let same_account = cached.pds.trim_end_matches('/') == pds.trim_end_matches('/')
+        && cached.handle.eq_ignore_ascii_case(handle);
+    same_account.then_some(cached.session)
+}
+
+fn read_session_cache(path: &Path) -> Option<BskyCachedSession> {
+    let bytes = std::fs::read(path).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Writes the session, readable only by the current user.
+fn store_session(path: &Path, pds: &str, handle: &str, session: &BskySession) -> Result<()> {
+    let cached = BskyCachedSession {
+        pds: pds.to_string(),
+        handle: handle.to_string(),
+        session: session.clone(),
+    };
+    let bytes = serde_json::to_vec_pretty(&cached).context("bsky: serialize session")?;
+    write_private(path, "bsky", &bytes)
+}
+
+/// Whether a JWT's `exp` claim is past, or within a minute of it. Unreadable tokens
+/// count as expired.
+fn jwt_expired(token: &str) -> bool {
+    let exp = token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|bytes| serde_json::from_slice::<JwtClaims>(&bytes).ok())
+        .map(|claims| claims.exp);
+    match exp {
+        Some(exp) => exp <= chrono::Utc::now().timestamp() + JWT_EXPIRY_LEEWAY_SECS,
+        None => true,
+    }
+}
+
+/// Finds the PDS hosting `handle`: handle → DID (DNS `_atproto` TXT record, then
+/// `https://<handle>/.well-known/atproto-did`) → DID document → `#atproto_pds` endpoint.
+async fn discover_pds(client: &reqwest::Client, handle: &str) -> Result<String> {
+    // Email logins can't be resolved.
+    if handle.contains('@') {
+        return Err(anyhow!("bsky: cannot discover PDS for {handle}"));
+    }
+    let did = match lookup_atproto_txt(handle).await {
+        Some(did) => did,
+        None => fetch_well_known_did(client, handle).await?,
+    };
+    let doc = fetch_did_document(client, &did).await?;
+    doc.service
+        .iter()
+        .find(|service| service.id == "#atproto_pds" || service.id == format!("{did}#atproto_pds"))
+        .and_then(|service| service.service_endpoint.as_str())
+        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+        .ok_or_else(|| anyhow!("bsky: no #atproto_pds service in DID document for {did}"))
+}
+
+async fn lookup_atproto_txt(handle: &str) -> Option<String> {
+    let resolver = TokioResolver::builder_tokio().ok()?.build().ok()?;
+    let lookup = resolver
+        .txt_lookup(format!("_atproto.{handle}."))
+        .await
+        .ok()?;
+    lookup
+        .answers()
+        .iter()
+        .find_map(|record| match &record.data {
+            RData::TXT(txt) => {
+                let value: String = txt
+                    .txt_data
+                    .iter()
+                    .map(|part| String::from_utf8_lossy(part))
+                    .collect();
+                value
+                    .strip_prefix("did=")
+                    .filter(|did| did.starts_with("did:"))
+                    .map(str::to_string)
+            }
+            _ => None,
+        })
+}
+
+async fn fetch_well_known_did(client: &reqwest::Client, handle: &str) -> Result<String> {
+    let resp = client
+        .get(format!("https://{handle}/.well-known/atproto-did"))
+        .send()
+        .await
+        .context("bsky: atproto-did request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("bsky: atproto-did", resp.status()).into());
+    }
+    let body = resp.text().await.context("bsky: read atproto-did")?;
+    let did = body.trim();
+    if !did.starts_with("did:") {
+        return Err(anyhow!("bsky: {handle} did not resolve to a DID"));
+    }
+    Ok(did.to_string())
+}
+
+async fn fetch_did_document(client: &reqwest::Client, did: &str) -> Result<DidDocument> {
+    let url = if did.starts_with("did:plc:") {
+        format!("{PLC_DIRECTORY}/{did}")
+    } else if let Some(host) = did.strip_prefix("did:web:") {
+        format!("https://{}/.well-known/did.json", host.replace("%3A", ":"))
+    } else {
+        return Err(anyhow!("bsky: unsupported DID method: {did}"));
+    };
+
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .context("bsky: DID document request failed")?;
+    if !resp.status().is_success() {
+        return Err(HttpError::new("bsky: DID document", resp.status()).into());
+    }
+    resp.json().await.context("bsky: parse DID document")
+}
+
+/// Splits `at://<repo>/<collection>/<rkey>` into its parts.
+fn parse_at_uri(uri: &str) -> Result<(&str, &str, &str)> {
+    let rest = uri
+        .strip_prefix("at://")
+        .ok_or_else(|| anyhow!("bsky: not an AT URI: {uri}"))?;
+    let mut parts = rest.splitn(3, '/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(repo), Some(collection), Some(rkey)) if !rkey.is_empty() => {
+            Ok((repo, collection, rkey))
+        }
+        _ => Err(anyhow!("bsky: malformed AT URI: {uri}")),
+    }
+}
+
+fn build_langs(post: &Post) -> Option<Vec<&str>> {
+    let langs: Vec<&str> = post
+        .options
+        .langs
+        .iter()
+        .take(BSKY_MAX_LANGS)
+        .map(String::as_str)
+        .collect();
+    (!langs.is_empty()).then_some(langs)
+}
+
+/// Bluesky has no free-form content warning; sensitive posts get the `graphic-media`
+/// self-label, which blurs media and warns before showing the post.
+fn build_self_labels(post: &Post) -> Option<BskySelfLabels> {
+    post.options.sensitive.then(|| BskySelfLabels {
+        typ: "com.atproto.label.defs#selfLabels",
+        values: vec![BskySelfLabel {
+            val: "graphic-media",
+        }],
+    })
+}
+
+fn build_reply_ref(reply_to: &ReplyTo) -> Result<BskyReplyRef> {
+    Ok(BskyReplyRef {
+        root: strong_ref(&reply_to.root)?,
+        parent: strong_ref(&reply_to.parent)?,
+    })
+}
+
+fn strong_ref(receipt: &Receipt) -> Result<BskyStrongRef> {
+    match receipt {
+        Receipt::Bluesky { uri, cid } => Ok(BskyStrongRef {
+            uri: uri.clone(),
+            cid: cid.clone(),
+        }),
+        other => Err(anyhow!("bsky: cannot reply to {other:?}")),
+    }
 }
 
 #[derive(Debug, Clone)]
-struct DetectedLink {
-    url: String,
+struct DetectedMention {
+    handle: String,
     start: usize,
     end: usize,
 }
@@ -97,53 +727,127 @@ struct LinkPreview {
     image: Option<String>,
 }
 
-fn detect_links(text: &str) -> Vec<DetectedLink> {
-    let mut finder = LinkFinder::new();
-    finder.kinds(&[LinkKind::Url]);
+/// Finds `@handle` mentions that start a word, with byte offsets covering the `@`.
+fn detect_mentions(text: &str) -> Vec<DetectedMention> {
+    let mut mentions = Vec::new();
+    let mut prev: Option<char> = None;
 
-    finder
-        .links(text)
-        .filter_map(|link| {
-            let uri = link.as_str();
-            if !(uri.starts_with("http://") || uri.starts_with("https://")) {
-                return None;
-            }
+    for (start, c) in text.char_indices() {
+        let at_boundary = starts_word(prev);
+        prev = Some(c);
+        if c != '@' || !at_boundary {
+            continue;
+        }
 
-            Some(DetectedLink {
-                url: uri.to_string(),
-                start: link.start(),
-                end: link.end(),
-            })
+        let rest = &text[start + 1..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+            .unwrap_or(rest.len());
+        let handle = rest[..len].trim_end_matches(['.', '-']);
+        if is_valid_handle(handle) {
+            mentions.push(DetectedMention {
+                handle: handle.to_ascii_lowercase(),
+                start,
+                end: start + 1 + handle.len(),
+            });
+        }
+    }
+
+    mentions
+}
+
+/// Checks the atproto handle syntax: two or more DNS labels, TLD not starting with a digit.
+fn is_valid_handle(handle: &str) -> bool {
+    let labels: Vec<&str> = handle.split('.').collect();
+    labels.len() >= 2
+        && handle.len() <= 253
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
         })
-        .collect()
+        && labels
+            .last()
+            .is_some_and(|tld| tld.starts_with(|c: char| c.is_ascii_alphabetic()))
 }
 
-fn build_bsky_facets(links: &[DetectedLink]) -> Option<Vec<BskyFacet>> {
-    if links.is_empty() {
-        return None;
+/// Resolves each mention's handle to a DID; handles that don't resolve stay plain text.
+async fn resolve_mentions(
+    client: &reqwest::Client,
+    pds: &str,
+    mentions: Vec<DetectedMention>,
+) -> Vec<(DetectedMention, String)> {
+    let lookups = mentions.into_iter().map(|mention| async move {
+        let did = resolve_handle(client, pds, &mention.handle).await.ok()?;
+        Some((mention, did))
+    });
+    join_all(lookups).await.into_iter().flatten().collect()
+}
+
+async fn resolve_handle(client: &reqwest::Client, pds: &str, handle: &str) -> Result<String> {
+    let resp = client
+        .get(format!(
+            "{}/xrpc/com.atproto.identity.resolveHandle",
+            pds.trim_end_matches('/')
+        ))
+        .query(&[("handle", handle)])
+        .send()
+        .await
+        .context("bsky: resolveHandle request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("bsky: resolveHandle", resp.status()).into());
     }
+    let out: BskyResolveHandleResp = resp.json().await.context("bsky: parse resolveHandle")?;
+    Ok(out.did)
+}
 
-    let facets = links
+fn build_bsky_facets(
+    links: &[DetectedLink],
+    mentions: &[(DetectedMention, String)],
+    tags: &[DetectedTag],
+) -> Option<Vec<BskyFacet>> {
+    let mut facets: Vec<BskyFacet> = links
         .iter()
         .map(|link| BskyFacet {
             index: BskyFacetIndex {
                 byte_start: link.start,
                 byte_end: link.end,
             },
-            features: vec![BskyFacetFeatureLink {
-                typ: "app.bsky.richtext.facet#link",
+            features: vec![BskyFacetFeature::Link {
                 uri: link.url.clone(),
             }],
         })
+        .chain(mentions.iter().map(|(mention, did)| BskyFacet {
+            index: BskyFacetIndex {
+                byte_start: mention.start,
+                byte_end: mention.end,
+            },
+            features: vec![BskyFacetFeature::Mention { did: did.clone() }],
+        }))
+        .chain(tags.iter().map(|tag| BskyFacet {
+            index: BskyFacetIndex {
+                byte_start: tag.start,
+                byte_end: tag.end,
+            },
+            features: vec![BskyFacetFeature::Tag {
+                tag: tag.tag.clone(),
+            }],
+        }))
         .collect();
 
+    if facets.is_empty() {
+        return None;
+    }
+    facets.sort_by_key(|facet| facet.index.byte_start);
     Some(facets)
 }
 
 fn build_bsky_external_embed(
     link: Option<&DetectedLink>,
     preview: Option<LinkPreview>,
-    thumb: Option<BskyThumb>,
+    thumb: Option<BskyUpload>,
 ) -> Option<BskyExternalEmbed> {
     let link = link?;
 
@@ -183,12 +887,11 @@ async fn fetch_link_preview(client: &reqwest::Client, url: &str) -> Option<LinkP
         return None;
     }
 
-    if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
-        if let Ok(ct) = content_type.to_str() {
-            if !ct.to_ascii_lowercase().contains("text/html") {
-                return None;
-            }
-        }
+    if let Some(content_type) = response.headers().get(CONTENT_TYPE)
+        && let Ok(ct) = content_type.to_str()
+        && !ct.to_ascii_lowercase().contains("text/html")
+    {
+        return None;
     }
 
     let bytes = response.bytes().await.ok()?;
@@ -251,13 +954,13 @@ async fn fetch_link_preview(client: &reqwest::Client, url: &str) -> Option<LinkP
         }
     }
 
-    if preview.title.is_none() {
-        if let Some(title_el) = document.select(&title_selector).next() {
-            let raw_title = title_el.text().collect::<String>();
-            let decoded = decode_html_entities(raw_title.trim()).to_string();
-            if let Some(normalized) = normalize_text(&decoded) {
-                preview.title = Some(normalized);
-            }
+    if preview.title.is_none()
+        && let Some(title_el) = document.select(&title_selector).next()
+    {
+        let raw_title = title_el.text().collect::<String>();
+        let decoded = decode_html_entities(raw_title.trim()).to_string();
+        if let Some(normalized) = normalize_text(&decoded) {
+            preview.title = Some(normalized);
         }
     }
 
@@ -268,13 +971,27 @@ async fn fetch_link_preview(client: &reqwest::Client, url: &str) -> Option<LinkP
     Some(preview)
 }
 
+/// Rejects attachments an `app.bsky.embed.images` embed can't carry.
+fn check_images(images: &[Image]) -> Result<()> {
+    if images.len() > BSKY_MAX_IMAGES {
+        return Err(anyhow!(
+            "bsky: at most {BSKY_MAX_IMAGES} images per post (got {})",
+            images.len()
+        ));
+    }
+    if images.iter().any(Image::is_video) {
+        return Err(anyhow!("bsky: video attachments are not supported"));
+    }
+    Ok(())
+}
+
 async fn fetch_thumbnail_blob(
     client: &reqwest::Client,
     page_url: &str,
     image_url: &str,
     pds: &str,
     access_token: &str,
-) -> Option<BskyThumb> {
+) -> Option<BskyBlob> {
     let resolved = resolve_url(page_url, image_url)?;
     let response = client
         .get(resolved.clone())
@@ -304,7 +1021,9 @@ async fn fetch_thumbnail_blob(
         return None;
     }
 
-    upload_blob(client, pds, access_token, bytes.to_vec(), &mime_type).await
+    upload_blob(client, pds, access_token, bytes.to_vec(), &mime_type)
+        .await
+        .ok()
 }
 
 async fn upload_blob(
@@ -313,7 +1032,7 @@ async fn upload_blob(
     access_token: &str,
     data: Vec<u8>,
     mime_type: &str,
-) -> Option<BskyThumb> {
+) -> Result<BskyBlob> {
     let url = format!(
         "{}/xrpc/com.atproto.repo.uploadBlob",
         pds.trim_end_matches('/')
@@ -326,19 +1045,19 @@ async fn upload_blob(
         .body(data)
         .send()
         .await
-        .ok()?;
+        .context("bsky: uploadBlob request failed")?;
 
     if !response.status().is_success() {
-        return None;
+        return Err(response_error("bsky: uploadBlob", response).await);
     }
 
-    let payload: UploadBlobResponse = response.json().await.ok()?;
+    let payload: UploadBlobResponse = response.json().await.context("bsky: parse uploadBlob")?;
 
-    Some(BskyThumb {
+    Ok(BskyBlob {
         typ: "blob",
         mime_type: payload.blob.mime_type,
         size: payload.blob.size,
-        reference: BskyThumbRef {
+        reference: BskyBlobRef {
             link: payload.blob.reference.link,
         },
     })
@@ -388,13 +1107,48 @@ fn clamp_text(text: String, limit: usize) -> String {
     truncated
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct BskySession {
     #[serde(rename = "accessJwt")]
     access_jwt: String,
+    #[serde(rename = "refreshJwt")]
+    refresh_jwt: String,
     did: String,
 }
 
+/// Body of a failed XRPC request.
+#[derive(Deserialize)]
+struct XrpcError {
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BskyCachedSession {
+    pds: String,
+    handle: String,
+    #[serde(flatten)]
+    session: BskySession,
+}
+
+#[derive(Deserialize)]
+struct DidDocument {
+    #[serde(default)]
+    service: Vec<DidService>,
+}
+
+#[derive(Deserialize)]
+struct DidService {
+    id: String,
+    #[serde(rename = "serviceEndpoint")]
+    service_endpoint: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    exp: i64,
+}
+
 #[derive(Serialize)]
 struct BskyPostRecord<'a> {
     #[serde(rename = "$type")]
@@ -405,9 +1159,37 @@ struct BskyPostRecord<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
     langs: Option<Vec<&'a str>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<BskySelfLabels>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply: Option<BskyReplyRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     facets: Option<Vec<BskyFacet>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    embed: Option<BskyExternalEmbed>,
+    embed: Option<BskyEmbed>,
+}
+
+#[derive(Serialize)]
+struct BskySelfLabels {
+    #[serde(rename = "$type")]
+    typ: &'static str,
+    values: Vec<BskySelfLabel>,
+}
+
+#[derive(Serialize)]
+struct BskySelfLabel {
+    val: &'static str,
+}
+
+#[derive(Serialize)]
+struct BskyReplyRef {
+    root: BskyStrongRef,
+    parent: BskyStrongRef,
+}
+
+#[derive(Serialize)]
+struct BskyStrongRef {
+    uri: String,
+    cid: String,
 }
 
 #[derive(Serialize)]
@@ -426,16 +1208,40 @@ struct BskyFacetIndex {
 }
 
 #[derive(Serialize)]
-struct BskyFacetFeatureLink {
-    #[serde(rename = "$type")]
-    typ: &'static str,
-    uri: String,
+#[serde(tag = "$type")]
+enum BskyFacetFeature {
+    #[serde(rename = "app.bsky.richtext.facet#link")]
+    Link { uri: String },
+    #[serde(rename = "app.bsky.richtext.facet#mention")]
+    Mention { did: String },
+    #[serde(rename = "app.bsky.richtext.facet#tag")]
+    Tag { tag: String },
 }
 
 #[derive(Serialize)]
 struct BskyFacet {
     index: BskyFacetIndex,
-    features: Vec<BskyFacetFeatureLink>,
+    features: Vec<BskyFacetFeature>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BskyEmbed {
+    External(BskyExternalEmbed),
+    Images(BskyImagesEmbed),
+}
+
+#[derive(Serialize)]
+struct BskyImagesEmbed {
+    #[serde(rename = "$type")]
+    typ: &'static str,
+    images: Vec<BskyImage>,
+}
+
+#[derive(Serialize)]
+struct BskyImage {
+    image: BskyUpload,
+    alt: String,
 }
 
 #[derive(Serialize)]
@@ -451,29 +1257,48 @@ struct BskyExternal {
     title: String,
     description: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    thumb: Option<BskyThumb>,
+    thumb: Option<BskyUpload>,
 }
 
+/// An uploaded blob, or in a dry run what would be uploaded: the image's MIME type
+/// or URL, and its size when known.
 #[derive(Serialize)]
-struct BskyThumb {
+#[serde(untagged)]
+enum BskyUpload {
+    Blob(BskyBlob),
+    Pending {
+        upload: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<usize>,
+    },
+}
+
+#[derive(Serialize)]
+struct BskyBlob {
     #[serde(rename = "$type")]
     typ: &'static str,
     #[serde(rename = "mimeType")]
     mime_type: String,
     size: usize,
     #[serde(rename = "ref")]
-    reference: BskyThumbRef,
+    reference: BskyBlobRef,
 }
 
 #[derive(Serialize)]
-struct BskyThumbRef {
+struct BskyBlobRef {
     #[serde(rename = "$link")]
     link: String,
 }
 
+#[derive(Deserialize)]
+struct BskyResolveHandleResp {
+    did: String,
+}
+
 #[derive(Deserialize)]
 struct BskyCreateRecordResp {
     uri: String,
+    cid: String,
 }
 
 #[derive(Deserialize)]
@@ -497,3 +1322,43 @@ struct UploadBlobRef {
     #[serde(rename = "$link")]
     link: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mentions(text: &str) -> Vec<(String, usize, usize)> {
+        detect_mentions(text)
+            .into_iter()
+            .map(|mention| (mention.handle, mention.start, mention.end))
+            .collect()
+    }
+
+    #[test]
+    fn mention_offsets_are_bytes() {
+        let text = "こんにちは @Alice.bsky.social さん";
+        assert_eq!(mentions(text), [("alice.bsky.social".to_string(), 16, 34)]);
+        assert_eq!(&text[16..34], "@Alice.bsky.social");
+    }
+
+    #[test]
+    fn trailing_punctuation_is_not_part_of_the_handle() {
+        assert_eq!(
+            mentions("Thanks @bob.example.com. And (@carol.dev)!"),
+            [
+                ("bob.example.com".to_string(), 7, 23),
+                ("carol.dev".to_string(), 30, 40),
+            ]
+        );
+    }
+
+    #[test]
+    fn mentions_inside_urls_and_addresses_are_ignored() {
+        assert!(mentions("https://bsky.app/profile/@alice.bsky.social me@example.com").is_empty());
+    }
+
+    #[test]
+    fn handles_need_a_valid_domain() {
+        assert!(mentions("@alice @bob.123 @-x.com").is_empty());
+    }
+}
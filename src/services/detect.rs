@@ -0,0 +1,172 @@
+use linkify::{LinkFinder, LinkKind};
+
+/// Longest hashtag (in characters) that is marked up; Bluesky's facet limit.
+const TAG_MAX_CHARS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub(super) struct DetectedLink {
+    pub(super) url: String,
+    pub(super) start: usize,
+    pub(super) end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct DetectedTag {
+    pub(super) tag: String,
+    pub(super) start: usize,
+    pub(super) end: usize,
+}
+
+/// Finds `http(s)` URLs, with byte offsets.
+pub(super) fn detect_links(text: &str) -> Vec<DetectedLink> {
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]);
+
+    finder
+        .links(text)
+        .filter_map(|link| {
+            let uri = link.as_str();
+            if !(uri.starts_with("http://") || uri.starts_with("https://")) {
+                return None;
+            }
+
+            Some(DetectedLink {
+                url: uri.to_string(),
+                start: link.start(),
+                end: link.end(),
+            })
+        })
+        .collect()
+}
+
+/// Whether a hashtag or mention may start after `prev`: at the start of the text, after
+/// whitespace, or after an opening parenthesis.
+pub(super) fn starts_word(prev: Option<char>) -> bool {
+    prev.is_none_or(|p| p.is_whitespace() || matches!(p, '(' | '（'))
+}
+
+/// Finds `#tag` hashtags that start a word, skipping `#` fragments inside detected links.
+///
+/// Trailing punctuation is not part of the tag, and all-digit tags such as `#1` are ignored.
+pub(super) fn detect_hashtags(text: &str, links: &[DetectedLink]) -> Vec<DetectedTag> {
+    let mut tags = Vec::new();
+    let mut prev: Option<char> = None;
+
+    for (start, c) in text.char_indices() {
+        let at_boundary = starts_word(prev);
+        prev = Some(c);
+        if !matches!(c, '#' | '＃') || !at_boundary {
+            continue;
+        }
+        if links
+            .iter()
+            .any(|link| start >= link.start && start < link.end)
+        {
+            continue;
+        }
+
+        let rest = &text[start + c.len_utf8()..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || is_invisible(c))
+            .unwrap_or(rest.len());
+        let tag = rest[..len].trim_end_matches(is_tag_punctuation);
+        if tag.is_empty()
+            || tag.starts_with('\u{fe0f}')
+            || tag.chars().count() > TAG_MAX_CHARS
+            || tag
+                .chars()
+                .all(|c| c.is_ascii_digit() || is_tag_punctuation(c))
+        {
+            continue;
+        }
+
+        tags.push(DetectedTag {
+            tag: tag.to_string(),
+            start,
+            end: start + c.len_utf8() + tag.len(),
+        });
+    }
+
+    tags
+}
+
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00ad}' | '\u{2060}' | '\u{200a}' | '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{20e2}'
+    )
+}
+
+fn is_tag_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(
+            c,
+            '…' | '、' | '。' | '，' | '！' | '？' | '：' | '；' | '「' | '」' | '（' | '）'
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashtags(text: &str) -> Vec<(String, usize, usize)> {
+        detect_hashtags(text, &detect_links(text))
+            .into_iter()
+            .map(|tag| (tag.tag, tag.start, tag.end))
+            .collect()
+    }
+
+    #[test]
+    fn hashtag_offsets_are_bytes() {
+        let text = "日本語 #ラーメン と #rust";
+        let tags = hashtags(text);
+        assert_eq!(
+            tags,
+            [
+                ("ラーメン".to_string(), 10, 23),
+                ("rust".to_string(), 28, 33),
+            ]
+        );
+        assert_eq!(&text[10..23], "#ラーメン");
+        assert_eq!(&text[28..33], "#rust");
+    }
+
+    #[test]
+    fn full_width_hash_starts_a_tag() {
+        let text = "＃東京";
+        assert_eq!(hashtags(text), [("東京".to_string(), 0, text.len())]);
+    }
+
+    #[test]
+    fn trailing_punctuation_is_not_part_of_the_tag() {
+        assert_eq!(
+            hashtags("Try #rust. Or #go! #日本。"),
+            [
+                ("rust".to_string(), 4, 9),
+                ("go".to_string(), 14, 17),
+                ("日本".to_string(), 19, 26),
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_may_follow_an_opening_parenthesis() {
+        assert_eq!(hashtags("(#rust)"), [("rust".to_string(), 1, 6)]);
+    }
+
+    #[test]
+    fn tag_inside_a_word_is_ignored() {
+        assert!(hashtags("C#rust and a#b").is_empty());
+    }
+
+    #[test]
+    fn url_fragments_are_not_tags() {
+        let text = "See https://example.com/page#section #docs";
+        assert_eq!(hashtags(text), [("docs".to_string(), 37, 42)]);
+    }
+
+    #[test]
+    fn numeric_tags_are_ignored() {
+        assert!(hashtags("#1 #2024").is_empty());
+    }
+}
@@ -1,27 +1,584 @@
 use anyhow::{Context, Result, anyhow};
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-use serde::Deserialize;
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use futures::{FutureExt, future::BoxFuture};
+use reqwest::{
+    StatusCode,
+    header::{AUTHORIZATION, CONTENT_TYPE, LINK},
+    multipart::{Form, Part},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+use super::{ConfigError, HttpError, Limits, PartialDelete, Receipt, Service};
+use crate::config::Config;
+use crate::lang;
+use crate::post::{Image, Post, ReplyTo, Visibility};
+use crate::thread::grapheme_len;
+
+/// Default status length on stock Mastodon instances.
+const MASTO_DEFAULT_CHAR_LIMIT: usize = 500;
+/// How often to check on media the server is still processing.
+const MEDIA_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for processing (mostly video transcoding) before giving up.
+const MEDIA_PROCESSING_TIMEOUT: Duration = Duration::from_secs(120);
+/// How long to wait for `/api/v2/instance` before falling back to the default limit.
+const INSTANCE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How far ahead Mastodon requires `scheduled_at` to be; it refuses anything sooner.
+pub const MIN_SCHEDULE_LEAD: TimeDelta = TimeDelta::minutes(5);
+
+pub struct Mastodon {
+    base: Option<String>,
+    token: Option<String>,
+    /// Raw `MASTODON_VISIBILITY`, used when a post doesn't set its own visibility.
+    visibility: Option<String>,
+}
+
+impl Mastodon {
+    pub fn new(base: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            base: Some(base.into()),
+            token: Some(token.into()),
+            visibility: None,
+        }
+    }
+
+    /// Sets the visibility for posts that don't specify one (default `public`).
+    pub fn with_visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = Some(visibility.to_string());
+        self
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            base: config.get("MASTODON_BASE_URL"),
+            token: config.get("MASTODON_ACCESS_TOKEN"),
+            visibility: config.get("MASTODON_VISIBILITY"),
+        }
+    }
+
+    /// Lists the account's scheduled statuses, soonest first.
+    pub async fn scheduled(&self) -> Result<Vec<ScheduledStatus>> {
+        self.validate()?;
+        let base = self.base.as_deref().unwrap_or_default();
+        let token = self.token.as_deref().unwrap_or_default();
+        list_scheduled_mastodon(base, token).await
+    }
+
+    fn default_visibility(&self) -> Result<Visibility, ConfigError> {
+        match self.visibility.as_deref() {
+            Some(raw) => raw
+                .parse()
+                .map_err(|e: anyhow::Error| ConfigError::Invalid {
+                    key: "MASTODON_VISIBILITY",
+                    reason: e.to_string(),
+                }),
+            None => Ok(Visibility::Public),
+        }
+    }
+}
+
+impl Service for Mastodon {
+    fn name(&self) -> &'static str {
+        "Mastodon"
+    }
+
+    fn key(&self) -> &'static str {
+        "masto"
+    }
+
+    fn supports_scheduling(&self) -> bool {
+        true
+    }
+
+    fn supports_restricted_visibility(&self) -> bool {
+        true
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            max_length: Some(MASTO_DEFAULT_CHAR_LIMIT),
+        }
+    }
+
+    fn fetch_limits(&self) -> BoxFuture<'_, Limits> {
+        async move {
+            let max_length = match self.base.as_deref() {
+                Some(base) => fetch_max_characters(base).await.ok(),
+                None => None,
+            };
+            Limits {
+                max_length: max_length.or(Some(MASTO_DEFAULT_CHAR_LIMIT)),
+            }
+        }
+        .boxed()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.base.is_none() {
+            return Err(ConfigError::Missing("MASTODON_BASE_URL"));
+        }
+        if self.token.is_none() {
+            return Err(ConfigError::Missing("MASTODON_ACCESS_TOKEN"));
+        }
+        self.default_visibility()?;
+        Ok(())
+    }
+
+    fn post<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<Receipt>> {
+        async move {
+            self.validate()?;
+            let base = self.base.as_deref().unwrap_or_default();
+            let token = self.token.as_deref().unwrap_or_default();
+            let visibility = self.default_visibility()?;
+            post_mastodon(base, token, visibility, post).await
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, receipts: &'a [Receipt]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.validate()?;
+            let base = self.base.as_deref().unwrap_or_default();
+            let token = self.token.as_deref().unwrap_or_default();
+            delete_mastodon(base, token, receipts).await
+        }
+        .boxed()
+    }
+
+    fn dry_run<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<serde_json::Value>> {
+        async move {
+            self.validate()?;
+            let base = self.base.as_deref().unwrap_or_default();
+            render_mastodon(base, self.default_visibility()?, post).await
+        }
+        .boxed()
+    }
+}
+
+#[derive(Deserialize)]
+struct MastoMediaResp {
+    id: String,
+    /// `null` until the server has finished processing the file.
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MastoInstance {
+    configuration: MastoInstanceConfiguration,
+}
+
+#[derive(Deserialize)]
+struct MastoInstanceConfiguration {
+    statuses: MastoStatusConfiguration,
+}
+
+#[derive(Deserialize)]
+struct MastoStatusConfiguration {
+    max_characters: usize,
+}
+
+/// A status the server will publish later.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledStatus {
+    pub id: String,
+    pub scheduled_at: DateTime<Utc>,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+struct MastoScheduledStatus {
+    id: String,
+    scheduled_at: DateTime<Utc>,
+    #[serde(default)]
+    params: MastoScheduledParams,
+}
+
+#[derive(Default, Deserialize)]
+struct MastoScheduledParams {
+    #[serde(default)]
+    text: String,
+}
 
 #[derive(Deserialize)]
 struct MastoResp {
+    id: String,
     url: Option<String>,
     uri: Option<String>,
 }
 
-pub async fn post_mastodon(base: &str, token: &str, text: &str) -> Result<String> {
+/// Publishes `post`, using `visibility` unless the post sets its own.
+pub async fn post_mastodon(
+    base: &str,
+    token: &str,
+    visibility: Visibility,
+    post: &Post,
+) -> Result<Receipt> {
+    check_poll(post)?;
     let client = reqwest::Client::new();
+
+    // Upload everything first, then wait, so large files are processed in parallel.
+    let mut uploads = Vec::with_capacity(post.images.len());
+    for image in &post.images {
+        uploads.push(upload_media(&client, base, token, image).await?);
+    }
+    let mut media_ids = Vec::with_capacity(uploads.len());
+    for media in uploads {
+        if media.url.is_none() {
+            wait_for_media(&client, base, token, &media.id).await?;
+        }
+        media_ids.push(media.id);
+    }
+
+    let form = build_status_form(post, visibility, &media_ids)?;
     let resp = client
         .post(format!("{}/api/v1/statuses", base.trim_end_matches('/')))
         .header(AUTHORIZATION, format!("Bearer {}", token))
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-        .form(&[("status", text), ("visibility", "public")])
+        .form(&form)
         .send()
         .await
         .context("mastodon: request failed")?;
 
     if !resp.status().is_success() {
-        return Err(anyhow!("mastodon: status={}", resp.status()));
+        return Err(HttpError::new("mastodon:", resp.status()).into());
+    }
+    // Scheduling returns a ScheduledStatus instead of a Status.
+    if post.options.scheduled_at.is_some() {
+        let out: MastoScheduledStatus = resp.json().await.context("mastodon: parse")?;
+        return Ok(Receipt::MastodonScheduled {
+            id: out.id,
+            scheduled_at: out.scheduled_at,
+        });
     }
     let out: MastoResp = resp.json().await.context("mastodon: parse")?;
-    Ok(out.url.or(out.uri).unwrap_or_default())
+    Ok(Receipt::Mastodon {
+        id: out.id,
+        url: out.url.or(out.uri).unwrap_or_default(),
+    })
+}
+
+/// Builds the form `post_mastodon` would send, without authenticating or uploading.
+pub async fn render_mastodon(
+    base: &str,
+    visibility: Visibility,
+    post: &Post,
+) -> Result<serde_json::Value> {
+    check_poll(post)?;
+    let media_ids: Vec<String> = (1..=post.images.len())
+        .map(|i| format!("<upload {i}>"))
+        .collect();
+    let form = build_status_form(post, visibility, &media_ids)?;
+    let limit = fetch_max_characters(base)
+        .await
+        .unwrap_or(MASTO_DEFAULT_CHAR_LIMIT);
+
+    Ok(json!({
+        "endpoint": format!("{}/api/v1/statuses", base.trim_end_matches('/')),
+        "characters": grapheme_len(&post.text),
+        "limit": limit,
+        "form": form,
+        "media": post
+            .images
+            .iter()
+            .map(|image| json!({
+                "mime_type": image.mime_type,
+                "size": image.data.len(),
+                "description": image.alt,
+                "focus": image.focus.map(|focus| focus.to_string()),
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+/// Mastodon rejects statuses that carry both a poll and attachments.
+fn check_poll(post: &Post) -> Result<()> {
+    if post.poll.is_some() && !post.images.is_empty() {
+        return Err(anyhow!(
+            "mastodon: a status cannot have both a poll and media"
+        ));
+    }
+    Ok(())
+}
+
+fn build_status_form<'a>(
+    post: &'a Post,
+    visibility: Visibility,
+    media_ids: &'a [String],
+) -> Result<Vec<(&'static str, Cow<'a, str>)>> {
+    let in_reply_to = match &post.reply_to {
+        Some(ReplyTo {
+            parent: Receipt::Mastodon { id, .. },
+            ..
+        }) => Some(id.as_str()),
+        Some(other) => return Err(anyhow!("mastodon: cannot reply to {:?}", other.parent)),
+        None => None,
+    };
+    let options = &post.options;
+    let visibility = options.visibility.unwrap_or(visibility);
+    let mut form = vec![
+        ("status", Cow::from(post.text.as_str())),
+        ("visibility", Cow::from(visibility.as_str())),
+    ];
+    if let Some(id) = in_reply_to {
+        form.push(("in_reply_to_id", id.into()));
+    }
+    if let Some(spoiler) = options.content_warning.as_deref() {
+        form.push(("spoiler_text", spoiler.into()));
+    }
+    if options.sensitive {
+        form.push(("sensitive", "true".into()));
+    }
+    // Mastodon takes ISO 639 codes, so `pt-BR` is sent as `pt`.
+    if let Some(lang) = options.langs.first().map(|l| lang::primary_subtag(l)) {
+        form.push(("language", lang.into()));
+    }
+    if let Some(at) = options.scheduled_at {
+        form.push((
+            "scheduled_at",
+            at.to_rfc3339_opts(SecondsFormat::Secs, true).into(),
+        ));
+    }
+    form.extend(media_ids.iter().map(|id| ("media_ids[]", id.into())));
+    if let Some(poll) = &post.poll {
+        form.extend(
+            poll.options
+                .iter()
+                .map(|option| ("poll[options][]", option.into())),
+        );
+        form.push(("poll[expires_in]", poll.expires_in.to_string().into()));
+        if poll.multiple {
+            form.push(("poll[multiple]", "true".into()));
+        }
+    }
+    Ok(form)
+}
+
+/// Deletes the statuses behind `receipts`, cancelling those that are still scheduled.
+///
+/// A status the server no longer has (404) counts as deleted, so an interrupted delete
+/// can be run again. When a later part fails, the error carries a [`PartialDelete`]
+/// with the parts that were deleted.
+pub async fn delete_mastodon(base: &str, token: &str, receipts: &[Receipt]) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut deleted = Vec::new();
+    for receipt in receipts {
+        if let Err(e) = delete_status(&client, base, token, receipt).await {
+            if deleted.is_empty() {
+                return Err(e);
+            }
+            return Err(e.context(PartialDelete { deleted }));
+        }
+        deleted.push(receipt.clone());
+    }
+    Ok(())
+}
+
+async fn delete_status(
+    client: &reqwest::Client,
+    base: &str,
+    token: &str,
+    receipt: &Receipt,
+) -> Result<()> {
+    let path = match receipt {
+        Receipt::Mastodon { id, .. } => format!("statuses/{id}"),
+        Receipt::MastodonScheduled { id, .. } => format!("scheduled_statuses/{id}"),
+        _ => return Err(anyhow!("mastodon: cannot delete {receipt:?}")),
+    };
+    let resp = client
+        .delete(format!("{}/api/v1/{path}", base.trim_end_matches('/')))
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .send()
+        .await
+        .context("mastodon: delete request failed")?;
+
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let error = anyhow::Error::new(HttpError::new("mastodon: delete", status));
+    match receipt {
+        // Once published, a scheduled status is gone and Mastodon doesn't say which
+        // status replaced it.
+        Receipt::MastodonScheduled { id, scheduled_at }
+            if status == StatusCode::NOT_FOUND && *scheduled_at <= Utc::now() =>
+        {
+            Err(error.context(format!(
+                "mastodon: scheduled status {id} was published at {}; Mastodon does not \
+                 link it to the new status, so delete that one on the server",
+                scheduled_at.format("%Y-%m-%d %H:%M:%S UTC")
+            )))
+        }
+        _ if status == StatusCode::NOT_FOUND => Ok(()),
+        _ => Err(error),
+    }
+}
+
+/// Lists the account's scheduled statuses, soonest first, following the `Link` header
+/// through every page.
+pub async fn list_scheduled_mastodon(base: &str, token: &str) -> Result<Vec<ScheduledStatus>> {
+    let client = reqwest::Client::new();
+    let mut url = format!(
+        "{}/api/v1/scheduled_statuses?limit=40",
+        base.trim_end_matches('/')
+    );
+    let mut scheduled = Vec::new();
+    loop {
+        let resp = client
+            .get(&url)
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .send()
+            .await
+            .context("mastodon: scheduled statuses request failed")?;
+
+        if !resp.status().is_success() {
+            return Err(HttpError::new("mastodon: scheduled statuses", resp.status()).into());
+        }
+        let next = resp
+            .headers()
+            .get(LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_link);
+        let out: Vec<MastoScheduledStatus> = resp
+            .json()
+            .await
+            .context("mastodon: parse scheduled statuses")?;
+        let empty = out.is_empty();
+        scheduled.extend(out.into_iter().map(|status| ScheduledStatus {
+            id: status.id,
+            scheduled_at: status.scheduled_at,
+            text: status.params.text,
+        }));
+        match next {
+            Some(next) if !empty => url = next,
+            _ => break,
+        }
+    }
+    scheduled.sort_by_key(|status| status.scheduled_at);
+    Ok(scheduled)
+}
+
+/// The `rel="next"` URL of a `Link` header.
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// Reads the instance's status length from `/api/v2/instance`.
+async fn fetch_max_characters(base: &str) -> Result<usize> {
+    let resp = reqwest::Client::new()
+        .get(format!("{}/api/v2/instance", base.trim_end_matches('/')))
+        .timeout(INSTANCE_TIMEOUT)
+        .send()
+        .await
+        .context("mastodon: instance request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("mastodon: instance", resp.status()).into());
+    }
+    let out: MastoInstance = resp.json().await.context("mastodon: parse instance")?;
+    Ok(out.configuration.statuses.max_characters)
+}
+
+/// Uploads an attachment with its description and focus point.
+///
+/// The server answers `202 Accepted` with a `null` URL when it processes the file in
+/// the background; see [`wait_for_media`].
+async fn upload_media(
+    client: &reqwest::Client,
+    base: &str,
+    token: &str,
+    image: &Image,
+) -> Result<MastoMediaResp> {
+    let ext = image.mime_type.rsplit('/').next().unwrap_or("bin");
+    let name = if image.is_video() { "video" } else { "image" };
+    let file = Part::bytes(image.data.clone())
+        .file_name(format!("{name}.{ext}"))
+        .mime_str(&image.mime_type)
+        .context("mastodon: media mime type")?;
+    let mut form = Form::new().part("file", file);
+    if !image.alt.is_empty() {
+        form = form.text("description", image.alt.clone());
+    }
+    if let Some(focus) = image.focus {
+        form = form.text("focus", focus.to_string());
+    }
+
+    let resp = client
+        .post(format!("{}/api/v2/media", base.trim_end_matches('/')))
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .multipart(form)
+        .send()
+        .await
+        .context("mastodon: media upload failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("mastodon: media", resp.status()).into());
+    }
+    resp.json().await.context("mastodon: parse media")
+}
+
+/// Polls `GET /api/v1/media/:id` until the attachment has a URL, since statuses can't
+/// attach media that is still processing.
+async fn wait_for_media(client: &reqwest::Client, base: &str, token: &str, id: &str) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        tokio::time::sleep(MEDIA_POLL_INTERVAL).await;
+        let resp = client
+            .get(format!("{}/api/v1/media/{id}", base.trim_end_matches('/')))
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .send()
+            .await
+            .context("mastodon: media status request failed")?;
+
+        match resp.status() {
+            // Still processing.
+            StatusCode::PARTIAL_CONTENT => {}
+            status if status.is_success() => {
+                let media: MastoMediaResp = resp.json().await.context("mastodon: parse media")?;
+                if media.url.is_some() {
+                    return Ok(());
+                }
+            }
+            status => return Err(HttpError::new("mastodon: media status", status).into()),
+        }
+
+        if started.elapsed() >= MEDIA_PROCESSING_TIMEOUT {
+            return Err(anyhow!(
+                "mastodon: media {id} still processing after {}s",
+                MEDIA_PROCESSING_TIMEOUT.as_secs()
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_link_picks_the_next_page() {
+        let header = "<https://example.social/api/v1/scheduled_statuses?limit=40&max_id=7>; \
+                      rel=\"next\", <https://example.social/api/v1/scheduled_statuses?\
+                      limit=40&min_id=9>; rel=\"prev\"";
+        assert_eq!(
+            next_link(header).as_deref(),
+            Some("https://example.social/api/v1/scheduled_statuses?limit=40&max_id=7")
+        );
+        assert_eq!(
+            next_link("<https://example.social/x?min_id=9>; rel=\"prev\""),
+            None
+        );
+    }
 }
@@ -1,3 +1,507 @@
 pub mod bluesky;
+mod detect;
 pub mod mastodon;
 pub mod nostr;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::{
+    FutureExt,
+    future::{BoxFuture, join_all},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::post::{Post, ReplyTo, Visibility};
+use crate::thread::{grapheme_len, split_text};
+
+/// Length constraints a network enforces on a single post.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Maximum post length, or `None` when the network has no practical limit.
+    pub max_length: Option<usize>,
+}
+
+impl Limits {
+    /// Returns the measured length when `text` is longer than the limit.
+    pub fn exceeded_by(&self, text: &str) -> Option<usize> {
+        let max = self.max_length?;
+        let len = grapheme_len(text);
+        (len > max).then_some(len)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("missing env {0}")]
+    Missing(&'static str),
+    #[error("invalid env {key}: {reason}")]
+    Invalid { key: &'static str, reason: String },
+}
+
+/// A network API answered with a non-success status.
+#[derive(Debug, Error)]
+#[error("{context} status={status}")]
+pub struct HttpError {
+    pub context: &'static str,
+    pub status: reqwest::StatusCode,
+}
+
+impl HttpError {
+    pub fn new(context: &'static str, status: reqwest::StatusCode) -> Self {
+        Self { context, status }
+    }
+}
+
+/// [`Service::delete`] retracted some parts before failing on a later one. The history
+/// drops the `deleted` receipts so running `dist delete` again resumes with the rest.
+#[derive(Debug, Error)]
+#[error("deleted {} of the post's parts before failing", deleted.len())]
+pub struct PartialDelete {
+    pub deleted: Vec<Receipt>,
+}
+
+/// Coarse classification of a failure, for scripts that react to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Missing or invalid credentials/configuration.
+    Config,
+    /// The network rejected the credentials (401/403).
+    Auth,
+    /// The network asked us to slow down (429).
+    RateLimited,
+    /// The network rejected the request itself (other 4xx).
+    Rejected,
+    /// The network failed to handle the request (5xx).
+    Server,
+    /// The request never completed: DNS, connect, timeout, or relay failures.
+    Network,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<HttpError>() {
+                return match e.status.as_u16() {
+                    401 | 403 => ErrorKind::Auth,
+                    429 => ErrorKind::RateLimited,
+                    400..=499 => ErrorKind::Rejected,
+                    500..=599 => ErrorKind::Server,
+                    _ => ErrorKind::Other,
+                };
+            }
+            if cause.is::<ConfigError>()
+                || cause.is::<nostr_sdk::key::Error>()
+                || cause.is::<nostr_sdk::nips::nip49::Error>()
+            {
+                return ErrorKind::Config;
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>()
+                && (e.is_connect() || e.is_timeout() || e.is_request())
+            {
+                return ErrorKind::Network;
+            }
+            if let Some(
+                nostr_sdk::client::Error::Relay(_) | nostr_sdk::client::Error::RelayPool(_),
+            ) = cause.downcast_ref::<nostr_sdk::client::Error>()
+            {
+                return ErrorKind::Network;
+            }
+            if cause.is::<nostr::RelayQuorumError>() {
+                return ErrorKind::Network;
+            }
+        }
+        ErrorKind::Other
+    }
+}
+
+/// A network that `dist` can cross-post to.
+pub trait Service: Send + Sync {
+    /// Human readable name used in output, e.g. `Bluesky`.
+    fn name(&self) -> &'static str;
+
+    /// Short identifier accepted by `--skip`, e.g. `bsky`.
+    fn key(&self) -> &'static str;
+
+    fn limits(&self) -> Limits;
+
+    /// Limits reported by the server, falling back to [`Service::limits`].
+    fn fetch_limits(&self) -> BoxFuture<'_, Limits> {
+        let limits = self.limits();
+        async move { limits }.boxed()
+    }
+
+    /// Checks that the credentials this service needs are configured.
+    fn validate(&self) -> Result<(), ConfigError>;
+
+    /// Whether the network can hold a post until its `scheduled_at` time; the others
+    /// refuse scheduled posts.
+    fn supports_scheduling(&self) -> bool {
+        false
+    }
+
+    /// Whether the network can limit who sees a post (`private` or `direct`
+    /// visibility); the others would publish it publicly.
+    fn supports_restricted_visibility(&self) -> bool {
+        false
+    }
+
+    /// Whether the network has native polls; without them, the options are listed in
+    /// the text.
+    fn supports_polls(&self) -> bool {
+        true
+    }
+
+    /// The text published for `post`, poll options included where they become text.
+    /// Length checks and thread splitting measure this.
+    fn text<'a>(&self, post: &'a Post) -> Cow<'a, str> {
+        if self.supports_polls() {
+            post.text.as_str().into()
+        } else {
+            post.text_with_poll()
+        }
+    }
+
+    /// Does the setup that may wait on the user, such as asking for a key's passphrase.
+    ///
+    /// Callers run it for each service in turn before sending anything, so a prompt
+    /// can't stall the requests already in flight to other networks.
+    fn prepare(&self) -> BoxFuture<'_, Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// Publishes `post` and returns the identifiers the network assigned to it.
+    ///
+    /// When `post.reply_to` is set, the post is published as a reply to those receipts.
+    fn post<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<Receipt>>;
+
+    /// Retracts previously published posts identified by `receipts`.
+    fn delete<'a>(&'a self, receipts: &'a [Receipt]) -> BoxFuture<'a, Result<()>>;
+
+    /// Finishes publishing a part whose receipt is not [complete](Receipt::is_complete),
+    /// returning the updated receipt. Receipts that are already complete are returned
+    /// unchanged.
+    fn complete<'a>(&'a self, receipt: &'a Receipt) -> BoxFuture<'a, Result<Receipt>> {
+        let receipt = receipt.clone();
+        async move { Ok(receipt) }.boxed()
+    }
+
+    /// Renders the request [`Service::post`] would send, without authenticating or
+    /// publishing anything.
+    fn dry_run<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<serde_json::Value>>;
+}
+
+/// Identifiers of a successfully published post.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "service", rename_all = "lowercase")]
+pub enum Receipt {
+    Bluesky {
+        uri: String,
+        cid: String,
+    },
+    Mastodon {
+        id: String,
+        url: String,
+    },
+    /// A Mastodon status waiting to be published by the server.
+    #[serde(rename = "mastodon_scheduled")]
+    MastodonScheduled {
+        /// Scheduled status id; it gets a new status id once published.
+        id: String,
+        scheduled_at: DateTime<Utc>,
+    },
+    Nostr {
+        id: String,
+        /// Relays that acknowledged the event.
+        relays: Vec<String>,
+        /// Relays that refused the event or couldn't be reached, with the reason.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        rejected: BTreeMap<String, String>,
+        /// The signed event as JSON, kept while fewer than the required relays accepted
+        /// it so a retry can resend it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        event: Option<String>,
+        /// `<kind>:<pubkey>:<d>` of an addressable event such as an article, which
+        /// relays delete by coordinate as well as by id.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        coordinate: Option<String>,
+    },
+}
+
+impl Receipt {
+    /// The canonical reference shown to users: AT URI, status URL, scheduled status id,
+    /// or event id.
+    pub fn link(&self) -> &str {
+        match self {
+            Receipt::Bluesky { uri, .. } => uri,
+            Receipt::Mastodon { url, .. } => url,
+            Receipt::MastodonScheduled { id, .. } => id,
+            Receipt::Nostr { id, .. } => id,
+        }
+    }
+
+    /// Whether the part is fully published. An incomplete part (a Nostr event short of
+    /// its relay quorum) is finished by [`Service::complete`] instead of posted again.
+    pub fn is_complete(&self) -> bool {
+        !matches!(self, Receipt::Nostr { event: Some(_), .. })
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Posted,
+    Skipped(ConfigError),
+    Failed(anyhow::Error),
+}
+
+/// Result of publishing to one network.
+#[derive(Debug)]
+pub struct ServiceResult {
+    pub service: &'static str,
+    /// The service's [`Service::key`].
+    pub key: &'static str,
+    /// Every part that was published, in thread order; may be non-empty on failure.
+    pub receipts: Vec<Receipt>,
+    pub outcome: Outcome,
+    /// Wall-clock time spent on this service.
+    pub elapsed: Duration,
+}
+
+/// Why `service` has to be left out of `post`, if it does: it would refuse the post's
+/// `scheduled_at`, or publish a followers-only or direct post publicly.
+pub fn unsupported(service: &dyn Service, post: &Post) -> Option<&'static str> {
+    if post.options.scheduled_at.is_some() && !service.supports_scheduling() {
+        return Some("scheduling unsupported");
+    }
+    if matches!(
+        post.options.visibility,
+        Some(Visibility::Private | Visibility::Direct)
+    ) && !service.supports_restricted_visibility()
+    {
+        return Some("visibility unsupported");
+    }
+    None
+}
+
+impl ServiceResult {
+    /// A failure before anything was published, e.g. from [`Service::prepare`].
+    pub fn failed(service: &dyn Service, error: anyhow::Error) -> Self {
+        Self {
+            service: service.name(),
+            key: service.key(),
+            receipts: Vec::new(),
+            outcome: Outcome::Failed(error),
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+/// Publishes `post` to every service concurrently.
+///
+/// Services whose credentials are missing are reported as [`Outcome::Skipped`]
+/// rather than failing the whole run.
+pub async fn publish(services: &[&dyn Service], post: &Post) -> Vec<ServiceResult> {
+    publish_all(services, post, false).await
+}
+
+/// Like [`publish`], but text longer than a service's limit is split into a numbered
+/// reply chain on that service. Images are attached to the first part only.
+pub async fn publish_thread(services: &[&dyn Service], post: &Post) -> Vec<ServiceResult> {
+    publish_all(services, post, true).await
+}
+
+async fn publish_all(services: &[&dyn Service], post: &Post, thread: bool) -> Vec<ServiceResult> {
+    let mut publishes = Vec::with_capacity(services.len());
+    for service in services {
+        let prepared = service.prepare().await;
+        publishes.push(async move {
+            match prepared {
+                Ok(()) => resume(*service, post, thread, Vec::new()).await,
+                Err(e) => ServiceResult::failed(*service, e),
+            }
+        });
+    }
+    join_all(publishes).await
+}
+
+/// Publishes `post` to a single service, skipping the parts already in `published`.
+///
+/// Used to retry a failed run without duplicating what reached the network: the
+/// remaining thread parts reply to the receipts from the earlier attempt.
+pub async fn resume(
+    service: &dyn Service,
+    post: &Post,
+    thread: bool,
+    published: Vec<Receipt>,
+) -> ServiceResult {
+    let mut result = ServiceResult {
+        service: service.name(),
+        key: service.key(),
+        receipts: published,
+        outcome: Outcome::Posted,
+        elapsed: Duration::ZERO,
+    };
+    let started = Instant::now();
+    if let Err(e) = service.validate() {
+        result.outcome = Outcome::Skipped(e);
+        return result;
+    }
+
+    // Poll options that become text are split with it, so the parts carry no poll.
+    let text = service.text(post);
+    let poll = post.poll.as_ref().filter(|_| service.supports_polls());
+    let parts = split_parts(service, text.into_owned(), thread).await;
+
+    if let Some(last) = result.receipts.last_mut()
+        && !last.is_complete()
+    {
+        match service.complete(last).await {
+            Ok(receipt) => *last = receipt,
+            Err(e) => {
+                if let Some(receipt) = incomplete_receipt(&e) {
+                    *last = receipt;
+                }
+                result.outcome = Outcome::Failed(e);
+                result.elapsed = started.elapsed();
+                return result;
+            }
+        }
+    }
+
+    let done = result.receipts.len();
+    for (i, text) in parts.into_iter().enumerate().skip(done) {
+        let reply_to = match result.receipts.first() {
+            Some(root) => Some(ReplyTo {
+                root: root.clone(),
+                parent: result
+                    .receipts
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| root.clone()),
+            }),
+            None => post.reply_to.clone(),
+        };
+        let part = Post {
+            text,
+            images: if i == 0 {
+                post.images.clone()
+            } else {
+                Vec::new()
+            },
+            reply_to,
+            options: post.options.clone(),
+            poll: if i == 0 { poll.cloned() } else { None },
+        };
+        match service.post(&part).await {
+            Ok(receipt) => result.receipts.push(receipt),
+            Err(e) => {
+                result.receipts.extend(incomplete_receipt(&e));
+                result.outcome = Outcome::Failed(e);
+                break;
+            }
+        }
+    }
+
+    result.elapsed = started.elapsed();
+    result
+}
+
+/// `text` split at the server's length limit when threading; the limit is only looked
+/// up then, since unthreaded posts are sent whole.
+async fn split_parts(service: &dyn Service, text: String, thread: bool) -> Vec<String> {
+    if !thread {
+        return vec![text];
+    }
+    match service.fetch_limits().await.max_length {
+        Some(limit) => split_text(&text, limit),
+        None => vec![text],
+    }
+}
+
+/// The receipt of a part that reached some of the network before `error`, so it is
+/// recorded and later finished rather than posted again.
+fn incomplete_receipt(error: &anyhow::Error) -> Option<Receipt> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<nostr::RelayQuorumError>())
+        .and_then(|e| e.receipt.clone())
+}
+
+/// What a dry run would have sent to one network.
+#[derive(Debug)]
+pub enum Preview {
+    /// One rendered request per part (a single part unless threading split the text).
+    Rendered(Vec<serde_json::Value>),
+    Skipped(ConfigError),
+    Failed(anyhow::Error),
+}
+
+#[derive(Debug)]
+pub struct ServicePreview {
+    pub service: &'static str,
+    pub key: &'static str,
+    pub preview: Preview,
+}
+
+/// Renders what [`publish`] (or [`publish_thread`] when `thread` is set) would send to
+/// each service, without authenticating or publishing.
+///
+/// Thread parts after the first are rendered without reply references, since those
+/// only exist once the earlier parts are published.
+pub async fn preview(services: &[&dyn Service], post: &Post, thread: bool) -> Vec<ServicePreview> {
+    let previews = services.iter().map(|service| async move {
+        let preview = match service.validate() {
+            Err(e) => Preview::Skipped(e),
+            Ok(()) => render_parts(*service, post, thread).await,
+        };
+        ServicePreview {
+            service: service.name(),
+            key: service.key(),
+            preview,
+        }
+    });
+
+    join_all(previews).await
+}
+
+async fn render_parts(service: &dyn Service, post: &Post, thread: bool) -> Preview {
+    let text = service.text(post);
+    let poll = post.poll.as_ref().filter(|_| service.supports_polls());
+    let parts = split_parts(service, text.into_owned(), thread).await;
+
+    let mut rendered = Vec::with_capacity(parts.len());
+    for (i, text) in parts.into_iter().enumerate() {
+        let part = Post {
+            text,
+            images: if i == 0 {
+                post.images.clone()
+            } else {
+                Vec::new()
+            },
+            reply_to: if i == 0 { post.reply_to.clone() } else { None },
+            options: post.options.clone(),
+            poll: if i == 0 { poll.cloned() } else { None },
+        };
+        match service.dry_run(&part).await {
+            Ok(value) => rendered.push(value),
+            Err(e) => return Preview::Failed(e),
+        }
+    }
+    Preview::Rendered(rendered)
+}
+
+/// Every supported network, built from the current configuration.
+pub fn registry(config: &Config) -> Vec<Box<dyn Service>> {
+    vec![
+        Box::new(bluesky::Bluesky::from_config(config)),
+        Box::new(mastodon::Mastodon::from_config(config)),
+        Box::new(nostr::Nostr::from_config(config)),
+    ]
+}
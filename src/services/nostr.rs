@@ -1,27 +1,1528 @@
-use anyhow::Result;
+use anyhow::{Context, Result, anyhow};
+use futures::{
+    FutureExt,
+    future::{BoxFuture, join_all},
+};
+use nostr_connect::client::{AuthUrlHandler, NostrConnect};
+use nostr_sdk::nips::nip05::{Nip05Address, Nip05Profile};
+use nostr_sdk::nips::nip96::{self, ServerConfig, UploadRequest, UploadResponse};
 use nostr_sdk::prelude::*;
+use reqwest::{
+    header::AUTHORIZATION,
+    multipart::{Form, Part},
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
-use tokio::time::sleep;
+use thiserror::Error;
 
-pub async fn post_nostr(nsec_or_hex: &str, relays: &[String], text: &str) -> Result<String> {
-    let keys = Keys::parse(nsec_or_hex)?;
-    let client = Client::new(keys);
+use super::detect::{detect_hashtags, detect_links, starts_word};
+use super::{ConfigError, HttpError, Limits, Receipt, Service};
+use crate::article::Article;
+use crate::config::{Config, project_dirs, write_private};
+use crate::lang;
+use crate::post::{Image, Poll, Post, ReplyTo};
 
-    for r in relays {
-        let url = match RelayUrl::parse(r) {
-            Ok(u) => u,
-            Err(_) => continue,
+/// NIP-32 namespace for language labels.
+const LANG_NAMESPACE: &str = "ISO-639-1";
+/// NIP-49 scrypt cost (2^16 rounds), the spec's recommended default.
+const NCRYPTSEC_LOG_N: u8 = 16;
+/// How long to wait for relays to accept the websocket connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for bootstrap relays to answer a relay-list query.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Web viewer that article teasers link to when `NOSTR_ARTICLE_VIEWER` is unset.
+const DEFAULT_ARTICLE_VIEWER: &str = "https://njump.me/";
+/// Most relay hints put in an `nprofile` reference for a NIP-05 mention.
+const MAX_RELAY_HINTS: usize = 3;
+/// How long to wait for a NIP-46 remote signer, which may need the user's approval.
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Relays queried for NIP-65 relay lists when `NOSTR_BOOTSTRAP_RELAYS` is unset.
+const DEFAULT_BOOTSTRAP_RELAYS: &[&str] = &[
+    "wss://purplepag.es",
+    "wss://relay.damus.io",
+    "wss://nos.lol",
+];
+
+pub struct Nostr {
+    /// `nsec`, hex, or NIP-49 `ncryptsec` key.
+    nsec: Option<String>,
+    /// Raw NIP-46 `bunker://` URI; when set, events are signed remotely instead of with `nsec`.
+    bunker: Option<String>,
+    /// Shell command that prints the `ncryptsec` passphrase; prompts on the terminal if unset.
+    passphrase_command: Option<String>,
+    /// Signer built on first use, so the passphrase is asked for (or the bunker
+    /// connected) once.
+    signer: OnceLock<Arc<dyn NostrSigner>>,
+    relays: Vec<String>,
+    media_server: Option<String>,
+    /// Raw `NOSTR_MIN_RELAYS`; one accepting relay is enough when unset.
+    min_relays: Option<String>,
+    /// Where to look up the user's NIP-65 relay list when `relays` is empty.
+    bootstrap_relays: Vec<String>,
+    /// URL prefix that turns an article's `naddr` into a web link.
+    article_viewer: Option<String>,
+    /// Raw `NOSTR_PUBKEY`, so dry runs can show the author without asking the bunker or
+    /// decrypting the key.
+    public_key: Option<String>,
+}
+
+/// Fewer relays acknowledged an event than the configured minimum.
+#[derive(Debug, Error)]
+#[error(
+    "nostr: {} of {required} required relays accepted the event{}",
+    accepted.len(),
+    format_rejections(rejected)
+)]
+pub struct RelayQuorumError {
+    pub required: usize,
+    pub accepted: Vec<String>,
+    pub rejected: BTreeMap<String, String>,
+    /// The incomplete receipt for the event, when there is one; [`Service::complete`]
+    /// resends it to the relays that don't have it yet.
+    pub receipt: Option<Receipt>,
+}
+
+fn format_rejections(rejected: &BTreeMap<String, String>) -> String {
+    if rejected.is_empty() {
+        return String::new();
+    }
+    let reasons: Vec<String> = rejected
+        .iter()
+        .map(|(relay, reason)| format!("{relay}: {reason}"))
+        .collect();
+    format!(" ({})", reasons.join("; "))
+}
+
+impl Nostr {
+    pub fn new(nsec_or_hex: impl Into<String>, relays: Vec<String>) -> Self {
+        Self {
+            nsec: Some(nsec_or_hex.into()),
+            bunker: None,
+            passphrase_command: None,
+            signer: OnceLock::new(),
+            relays,
+            media_server: None,
+            min_relays: None,
+            bootstrap_relays: default_bootstrap_relays(),
+            article_viewer: None,
+            public_key: None,
+        }
+    }
+
+    /// Signs events through the NIP-46 remote signer at `bunker_uri` (`bunker://...`),
+    /// so no private key is stored locally.
+    pub fn bunker(bunker_uri: impl Into<String>, relays: Vec<String>) -> Self {
+        Self {
+            nsec: None,
+            bunker: Some(bunker_uri.into()),
+            passphrase_command: None,
+            signer: OnceLock::new(),
+            relays,
+            media_server: None,
+            min_relays: None,
+            bootstrap_relays: default_bootstrap_relays(),
+            article_viewer: None,
+            public_key: None,
+        }
+    }
+
+    /// Sets the relays queried for the NIP-65 relay list when no relays are configured.
+    pub fn with_bootstrap_relays(mut self, relays: Vec<String>) -> Self {
+        self.bootstrap_relays = relays;
+        self
+    }
+
+    /// Reads the passphrase for an `ncryptsec` key from the output of `command` (run
+    /// through the shell) instead of prompting on the terminal.
+    pub fn with_passphrase_command(mut self, command: impl Into<String>) -> Self {
+        self.passphrase_command = Some(command.into());
+        self
+    }
+
+    /// Requires at least `count` relays to acknowledge a post for it to succeed.
+    pub fn with_min_relays(mut self, count: usize) -> Self {
+        self.min_relays = Some(count.to_string());
+        self
+    }
+
+    /// Sets the URL prefix (e.g. `https://habla.news/a/`) that article links put before
+    /// the article's `naddr`.
+    pub fn with_article_viewer(mut self, viewer: impl Into<String>) -> Self {
+        self.article_viewer = Some(viewer.into());
+        self
+    }
+
+    /// Sets the public key (`npub` or hex) that dry runs show as the author when the
+    /// key is held by a bunker or encrypted.
+    pub fn with_public_key(mut self, public_key: impl Into<String>) -> Self {
+        self.public_key = Some(public_key.into());
+        self
+    }
+
+    /// Sets the NIP-96 server used to host image attachments.
+    pub fn with_media_server(mut self, server: impl Into<String>) -> Self {
+        self.media_server = Some(server.into());
+        self
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            nsec: config.get("NOSTR_NSEC"),
+            bunker: config.get("NOSTR_BUNKER"),
+            passphrase_command: config.get("NOSTR_PASSPHRASE_COMMAND"),
+            signer: OnceLock::new(),
+            relays: config.get_list("NOSTR_RELAYS"),
+            media_server: config.get("NOSTR_MEDIA_SERVER"),
+            min_relays: config.get("NOSTR_MIN_RELAYS"),
+            bootstrap_relays: Some(config.get_list("NOSTR_BOOTSTRAP_RELAYS"))
+                .filter(|relays| !relays.is_empty())
+                .unwrap_or_else(default_bootstrap_relays),
+            article_viewer: config.get("NOSTR_ARTICLE_VIEWER"),
+            public_key: config.get("NOSTR_PUBKEY"),
+        }
+    }
+
+    /// The remote signer from `NOSTR_BUNKER`, or the local key from `NOSTR_NSEC`.
+    async fn signer(&self) -> Result<Arc<dyn NostrSigner>> {
+        if let Some(signer) = self.signer.get() {
+            return Ok(signer.clone());
+        }
+        let signer: Arc<dyn NostrSigner> = match self.bunker_uri()? {
+            Some(uri) => {
+                let app_keys = load_app_keys()?;
+                let mut connect = NostrConnect::new(uri, app_keys, SIGNER_TIMEOUT, None)
+                    .context("nostr: connect to NOSTR_BUNKER")?;
+                connect.auth_url_handler(PrintAuthUrl);
+                Arc::new(connect)
+            }
+            None => Arc::new(self.keys().await?),
+        };
+        Ok(self.signer.get_or_init(|| signer).clone())
+    }
+
+    fn bunker_uri(&self) -> Result<Option<NostrConnectURI>, ConfigError> {
+        let Some(raw) = self.bunker.as_deref() else {
+            return Ok(None);
+        };
+        let invalid = |reason: String| ConfigError::Invalid {
+            key: "NOSTR_BUNKER",
+            reason,
+        };
+        let raw = raw.trim();
+        if !raw.starts_with("bunker://") {
+            return Err(invalid("expected a bunker:// URI".to_string()));
+        }
+        NostrConnectURI::parse(raw)
+            .map(Some)
+            .map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Parses the configured key, decrypting an `ncryptsec` key.
+    ///
+    /// The passphrase prompt or command and the scrypt decryption block, so they run on
+    /// the blocking pool.
+    async fn keys(&self) -> Result<Keys> {
+        let secret = self
+            .nsec
+            .as_deref()
+            .ok_or(ConfigError::Missing("NOSTR_NSEC"))?;
+        let keys = if secret.starts_with("ncryptsec1") {
+            let encrypted = EncryptedSecretKey::from_bech32(secret)?;
+            let command = self.passphrase_command.clone();
+            let secret_key = tokio::task::spawn_blocking(move || {
+                let passphrase = read_passphrase(command.as_deref())?;
+                encrypted
+                    .decrypt(&passphrase)
+                    .context("nostr: decrypt NOSTR_NSEC (wrong passphrase?)")
+            })
+            .await
+            .context("nostr: read NOSTR_NSEC passphrase")??;
+            Keys::new(secret_key)
+        } else {
+            Keys::parse(secret)?
+        };
+        Ok(keys)
+    }
+
+    /// The public key, if it is known without signing in: `NOSTR_PUBKEY`, or derived
+    /// from an unencrypted `NOSTR_NSEC`.
+    fn known_public_key(&self) -> Result<Option<PublicKey>, ConfigError> {
+        if let Some(public_key) = self.configured_public_key()? {
+            return Ok(Some(public_key));
+        }
+        match self.nsec.as_deref() {
+            Some(secret) if self.bunker.is_none() && !secret.starts_with("ncryptsec1") => {
+                Keys::parse(secret)
+                    .map(|keys| Some(keys.public_key()))
+                    .map_err(|e| ConfigError::Invalid {
+                        key: "NOSTR_NSEC",
+                        reason: e.to_string(),
+                    })
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn configured_public_key(&self) -> Result<Option<PublicKey>, ConfigError> {
+        let Some(raw) = self.public_key.as_deref() else {
+            return Ok(None);
+        };
+        PublicKey::parse(raw.trim())
+            .map(Some)
+            .map_err(|e| ConfigError::Invalid {
+                key: "NOSTR_PUBKEY",
+                reason: e.to_string(),
+            })
+    }
+
+    /// The public key and relays for a dry run, without signing in. The key is `None`
+    /// when only the signer knows it, which requires `NOSTR_RELAYS`.
+    async fn preview_author(&self) -> Result<(Option<PublicKey>, Vec<String>)> {
+        match self.known_public_key()? {
+            Some(public_key) => Ok((Some(public_key), self.write_relays(public_key).await?)),
+            None if !self.relays.is_empty() => Ok((None, self.relays.clone())),
+            None => Err(
+                anyhow::Error::new(ConfigError::Missing("NOSTR_PUBKEY")).context(
+                    "nostr: NOSTR_RELAYS is empty, and looking up the NIP-65 relay list needs \
+                 the public key",
+                ),
+            ),
+        }
+    }
+
+    /// The configured relays, or the write relays from the user's NIP-65 relay list
+    /// when `NOSTR_RELAYS` is empty.
+    async fn write_relays(&self, public_key: PublicKey) -> Result<Vec<String>> {
+        if !self.relays.is_empty() {
+            return Ok(self.relays.clone());
+        }
+        let relays = fetch_write_relays(public_key, &self.bootstrap_relays).await?;
+        if relays.is_empty() {
+            return Err(anyhow::Error::new(ConfigError::Missing("NOSTR_RELAYS"))
+                .context("nostr: no NIP-65 relay list found on the bootstrap relays"));
+        }
+        Ok(relays)
+    }
+
+    /// Publishes `NOSTR_RELAYS` as the user's NIP-65 relay list (kind 10002), to those
+    /// relays and the bootstrap relays so other clients can discover them.
+    pub async fn publish_relay_list(&self) -> Result<Receipt> {
+        self.validate()?;
+        if self.relays.is_empty() {
+            return Err(ConfigError::Missing("NOSTR_RELAYS").into());
+        }
+        let signer = self.signer().await?;
+
+        let urls = self
+            .relays
+            .iter()
+            .map(|relay| {
+                RelayUrl::parse(relay).with_context(|| format!("nostr: invalid relay URL {relay}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let builder = EventBuilder::relay_list(urls.into_iter().map(|url| (url, None)));
+
+        let mut targets = self.relays.clone();
+        for relay in &self.bootstrap_relays {
+            if !targets.contains(relay) {
+                targets.push(relay.clone());
+            }
+        }
+        send(&signer, &targets, builder).await?.into_receipt(1)
+    }
+
+    /// Publishes `article` as a NIP-23 long-form event (kind 30023).
+    pub async fn publish_article(&self, article: &Article) -> Result<Receipt> {
+        self.validate()?;
+        let signer = self.signer().await?;
+        let min_relays = self.min_relays()?;
+        let relays = self.write_relays(public_key(&signer).await?).await?;
+        send(&signer, &relays, build_article(article)?)
+            .await?
+            .into_receipt(min_relays)
+    }
+
+    /// Web link to `article`: `NOSTR_ARTICLE_VIEWER` followed by its `naddr`, with the
+    /// first write relay as a hint. Known before the article is published.
+    pub async fn article_link(&self, article: &Article) -> Result<String> {
+        self.validate()?;
+        let public_key = public_key(&self.signer().await?).await?;
+        let relays = self.write_relays(public_key).await?;
+        self.link(article, Some(public_key), &relays)
+    }
+
+    /// [`Nostr::article_link`] for dry runs, which does not sign in. The `naddr` is shown
+    /// as `<naddr>` when only the signer knows the public key.
+    pub async fn preview_article_link(&self, article: &Article) -> Result<String> {
+        self.validate()?;
+        let (public_key, relays) = self.preview_author().await?;
+        self.link(article, public_key, &relays)
+    }
+
+    fn link(
+        &self,
+        article: &Article,
+        public_key: Option<PublicKey>,
+        relays: &[String],
+    ) -> Result<String> {
+        let naddr = match public_key {
+            Some(public_key) => {
+                let coordinate = Coordinate::new(Kind::LongFormTextNote, public_key)
+                    .identifier(article.identifier.clone());
+                let hint = relays.first().and_then(|relay| RelayUrl::parse(relay).ok());
+                Nip19Coordinate::new(coordinate, hint).to_bech32()?
+            }
+            None => "<naddr>".to_string(),
+        };
+
+        let viewer = self
+            .article_viewer
+            .as_deref()
+            .unwrap_or(DEFAULT_ARTICLE_VIEWER);
+        let separator = if viewer.ends_with('/') { "" } else { "/" };
+        Ok(format!("{viewer}{separator}{naddr}"))
+    }
+
+    /// The unsigned event [`Nostr::publish_article`] would publish, with its relays.
+    pub async fn render_article(&self, article: &Article) -> Result<serde_json::Value> {
+        self.validate()?;
+        let (public_key, relays) = self.preview_author().await?;
+        Ok(serde_json::json!({
+            "relays": relays,
+            "event": render_event(build_article(article)?, public_key)?,
+        }))
+    }
+
+    fn min_relays(&self) -> Result<usize, ConfigError> {
+        let Some(raw) = self.min_relays.as_deref() else {
+            return Ok(1);
+        };
+        let invalid = |reason: String| ConfigError::Invalid {
+            key: "NOSTR_MIN_RELAYS",
+            reason,
         };
-        let _ = client.add_relay(url).await;
+        let count: usize = raw
+            .trim()
+            .parse()
+            .map_err(|_| invalid(format!("{raw:?} is not a number")))?;
+        if count == 0 {
+            return Err(invalid("must be at least 1".to_string()));
+        }
+        if !self.relays.is_empty() && count > self.relays.len() {
+            return Err(invalid(format!(
+                "{count} exceeds the {} configured relays",
+                self.relays.len()
+            )));
+        }
+        Ok(count)
+    }
+}
+
+impl Service for Nostr {
+    fn name(&self) -> &'static str {
+        "Nostr"
+    }
+
+    fn key(&self) -> &'static str {
+        "nostr"
+    }
+
+    fn limits(&self) -> Limits {
+        Limits { max_length: None }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bunker_uri()?.is_none() && self.nsec.is_none() {
+            return Err(ConfigError::Missing("NOSTR_NSEC"));
+        }
+        self.min_relays()?;
+        self.configured_public_key()?;
+        Ok(())
+    }
+
+    /// Asks for the `ncryptsec` passphrase, or sets up the bunker connection, up front.
+    fn prepare(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            if self.validate().is_ok() {
+                self.signer().await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn post<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<Receipt>> {
+        async move {
+            self.validate()?;
+            let signer = self.signer().await?;
+            let min_relays = self.min_relays()?;
+            let relays = self.write_relays(public_key(&signer).await?).await?;
+            post_nostr(
+                &signer,
+                &relays,
+                min_relays,
+                self.media_server.as_deref(),
+                post,
+            )
+            .await
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, receipts: &'a [Receipt]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.validate()?;
+            let signer = self.signer().await?;
+            let relays = self.write_relays(public_key(&signer).await?).await?;
+            delete_nostr(&signer, &relays, receipts).await
+        }
+        .boxed()
+    }
+
+    fn complete<'a>(&'a self, receipt: &'a Receipt) -> BoxFuture<'a, Result<Receipt>> {
+        async move {
+            let Receipt::Nostr {
+                relays: accepted,
+                event: Some(event),
+                ..
+            } = receipt
+            else {
+                return Ok(receipt.clone());
+            };
+            self.validate()?;
+            let min_relays = self.min_relays()?;
+            let event = Event::from_json(event).context("nostr: parse the unsent event")?;
+            let relays = self.write_relays(event.pubkey).await?;
+            resend(&relays, min_relays, accepted, event).await
+        }
+        .boxed()
+    }
+
+    fn dry_run<'a>(&'a self, post: &'a Post) -> BoxFuture<'a, Result<serde_json::Value>> {
+        async move {
+            self.validate()?;
+            let (public_key, relays) = self.preview_author().await?;
+            render_nostr(public_key, &relays, self.media_server.as_deref(), post).await
+        }
+        .boxed()
+    }
+}
+
+/// Publishes `post`, succeeding once at least `min_relays` relays acknowledge it.
+///
+/// `signer` is either local [`Keys`] or a remote signer such as [`NostrConnect`].
+pub async fn post_nostr(
+    signer: &Arc<dyn NostrSigner>,
+    relays: &[String],
+    min_relays: usize,
+    media_server: Option<&str>,
+    post: &Post,
+) -> Result<Receipt> {
+    if post.options.scheduled_at.is_some() {
+        return Err(anyhow!("nostr: scheduled posts are not supported"));
+    }
+    let http = reqwest::Client::new();
+    let mentions = resolve_mentions(&http, detect_mentions(&post.text)).await;
+
+    let mut attachments = Vec::with_capacity(post.images.len());
+    if !post.images.is_empty() {
+        let server = media_server
+            .ok_or_else(|| anyhow!("nostr: NOSTR_MEDIA_SERVER is required for images"))?;
+        let server_config = fetch_server_config(&http, server).await?;
+        for image in &post.images {
+            attachments.push(upload_image(&http, signer, &server_config, image).await?);
+        }
+    }
+
+    let builder = build_note(post, relays, &mentions, attachments)?;
+    send(signer, relays, builder)
+        .await?
+        .into_receipt(min_relays)
+}
+
+/// Builds the unsigned event `post_nostr` would publish, without uploading or connecting
+/// to relays. NIP-05 mentions are still resolved.
+///
+/// Image URLs are shown as `<upload N>` placeholders in the content and `imeta` tags,
+/// and the author as `<signer>` when `public_key` is `None`.
+pub async fn render_nostr(
+    public_key: Option<PublicKey>,
+    relays: &[String],
+    media_server: Option<&str>,
+    post: &Post,
+) -> Result<serde_json::Value> {
+    if post.options.scheduled_at.is_some() {
+        return Err(anyhow!("nostr: scheduled posts are not supported"));
+    }
+    let attachments = post
+        .images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let url = format!("<upload {}>", i + 1);
+            let mut fields = vec![format!("url {url}"), format!("m {}", image.mime_type)];
+            if !image.alt.is_empty() {
+                fields.push(format!("alt {}", image.alt));
+            }
+            (url, Tag::custom(TagKind::custom("imeta"), fields))
+        })
+        .collect();
+
+    let mentions = resolve_mentions(&reqwest::Client::new(), detect_mentions(&post.text)).await;
+    let builder = build_note(post, relays, &mentions, attachments)?;
+    Ok(serde_json::json!({
+        "relays": relays,
+        "media_server": media_server,
+        "event": render_event(builder, public_key)?,
+    }))
+}
+
+/// The unsigned event `builder` makes for `public_key`. Without a key the event is built
+/// for a throwaway one and the author shown as `<signer>`; its id depends on the author,
+/// so it is left out.
+fn render_event(builder: EventBuilder, public_key: Option<PublicKey>) -> Result<serde_json::Value> {
+    let Some(public_key) = public_key else {
+        let mut event = serde_json::to_value(builder.build(Keys::generate().public_key()))?;
+        event["pubkey"] = "<signer>".into();
+        if let Some(fields) = event.as_object_mut() {
+            fields.remove("id");
+        }
+        return Ok(event);
+    };
+    Ok(serde_json::to_value(builder.build(public_key))?)
+}
+
+/// Publishes a NIP-09 deletion request for `receipts` to `relays` and to every relay
+/// that originally accepted them.
+pub async fn delete_nostr(
+    signer: &Arc<dyn NostrSigner>,
+    relays: &[String],
+    receipts: &[Receipt],
+) -> Result<()> {
+    let mut targets = relays.to_vec();
+    let mut request = EventDeletionRequest::new();
+    for receipt in receipts {
+        let Receipt::Nostr {
+            id,
+            relays,
+            coordinate,
+            ..
+        } = receipt
+        else {
+            return Err(anyhow!("nostr: cannot delete {receipt:?}"));
+        };
+        request = request.id(EventId::parse(id)?);
+        // A later version of an article has a new id, so delete its address too (NIP-09).
+        if let Some(coordinate) = coordinate {
+            request = request.coordinate(Coordinate::parse(coordinate)?);
+        }
+        for relay in relays {
+            if !targets.contains(relay) {
+                targets.push(relay.clone());
+            }
+        }
+    }
+
+    let published = send(signer, &targets, EventBuilder::delete(request)).await?;
+    if published.accepted.is_empty() {
+        return Err(RelayQuorumError {
+            required: 1,
+            accepted: published.accepted,
+            rejected: published.rejected,
+            receipt: None,
+        })
+        .context("nostr: no relay accepted the deletion request");
+    }
+    Ok(())
+}
+
+/// Encrypts an `nsec` or hex key as a NIP-49 `ncryptsec` protected by `passphrase`.
+pub fn encrypt_key(secret: &str, passphrase: &str) -> Result<String> {
+    let keys = Keys::parse(secret)?;
+    let encrypted = EncryptedSecretKey::new(
+        keys.secret_key(),
+        passphrase,
+        NCRYPTSEC_LOG_N,
+        KeySecurity::Unknown,
+    )?;
+    Ok(encrypted.to_bech32()?)
+}
+
+/// Runs `command` through the shell and returns its first output line, or prompts on
+/// the terminal when no command is configured.
+fn read_passphrase(command: Option<&str>) -> Result<String> {
+    let Some(command) = command else {
+        return rpassword::prompt_password("Nostr key passphrase: ").map_err(|e| {
+            anyhow::Error::new(ConfigError::Missing("NOSTR_PASSPHRASE_COMMAND")).context(format!(
+                "nostr: NOSTR_NSEC is encrypted and no terminal is available ({e})"
+            ))
+        });
+    };
+
+    #[cfg(windows)]
+    let mut shell = std::process::Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    #[cfg(not(windows))]
+    let mut shell = std::process::Command::new("sh");
+    #[cfg(not(windows))]
+    shell.arg("-c");
+
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("nostr: run NOSTR_PASSPHRASE_COMMAND")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "nostr: NOSTR_PASSPHRASE_COMMAND failed ({})",
+            output.status
+        ));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .context("nostr: NOSTR_PASSPHRASE_COMMAND printed invalid UTF-8")?;
+    Ok(stdout.lines().next().unwrap_or_default().to_string())
+}
+
+async fn public_key(signer: &Arc<dyn NostrSigner>) -> Result<PublicKey> {
+    signer
+        .get_public_key()
+        .await
+        .context("nostr: get public key from signer")
+}
+
+/// Prints the URL a remote signer asks the user to open to approve a request.
+#[derive(Debug)]
+struct PrintAuthUrl;
+
+impl AuthUrlHandler for PrintAuthUrl {
+    fn on_auth_url(&self, auth_url: Url) -> BoxedFuture<'_, nostr_connect::prelude::Result<()>> {
+        Box::pin(async move {
+            eprintln!("Nostr: approve the request in your remote signer: {auth_url}");
+            Ok(())
+        })
+    }
+}
+
+fn app_keys_path() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().join("nostr-connect.key"))
+}
+
+/// The client keys `dist` uses to talk to the remote signer, created on first use.
+///
+/// Keeping them stable lets the signer remember that it already authorized `dist`, so
+/// a one-time `secret` in the bunker URI is only needed for the first connection.
+fn load_app_keys() -> Result<Keys> {
+    let path = app_keys_path().ok_or_else(|| anyhow!("nostr: no home directory"))?;
+    if let Ok(secret) = std::fs::read_to_string(&path) {
+        return Keys::parse(secret.trim())
+            .with_context(|| format!("nostr: parse {}", path.display()));
+    }
+    let keys = Keys::generate();
+    store_app_keys(&path, &keys)?;
+    Ok(keys)
+}
+
+/// Writes the client key, readable only by the current user.
+fn store_app_keys(path: &Path, keys: &Keys) -> Result<()> {
+    write_private(path, "nostr", keys.secret_key().to_secret_hex().as_bytes())
+}
+
+fn default_bootstrap_relays() -> Vec<String> {
+    DEFAULT_BOOTSTRAP_RELAYS
+        .iter()
+        .map(|relay| relay.to_string())
+        .collect()
+}
+
+/// Looks up `author`'s newest NIP-65 relay list on `bootstrap` and returns the relays
+/// marked for writing (unmarked entries are both read and write).
+async fn fetch_write_relays(author: PublicKey, bootstrap: &[String]) -> Result<Vec<String>> {
+    let client = Client::default();
+    for relay in bootstrap {
+        if let Ok(url) = RelayUrl::parse(relay) {
+            let _ = client.add_relay(url).await;
+        }
     }
 
-    client.connect().await;
+    let connected = client.try_connect(CONNECT_TIMEOUT).await;
+    if connected.success.is_empty() {
+        client.disconnect().await;
+        return Err(anyhow!("nostr: could not reach any bootstrap relay"));
+    }
+    let filter = Filter::new().author(author).kind(Kind::RelayList).limit(1);
+    let events = client
+        .fetch_events_from(connected.success, filter, FETCH_TIMEOUT)
+        .await;
+    client.disconnect().await;
+
+    let Some(event) = events?.into_iter().max_by_key(|event| event.created_at) else {
+        return Ok(Vec::new());
+    };
+    Ok(nip65::extract_relay_list(&event)
+        .filter(|(_, metadata)| !matches!(metadata, Some(RelayMetadata::Read)))
+        .map(|(url, _)| url.to_string())
+        .collect())
+}
+
+/// Where an event ended up after [`send`].
+struct Published {
+    event: Event,
+    accepted: Vec<String>,
+    rejected: BTreeMap<String, String>,
+}
+
+impl Published {
+    /// The receipt for the event, or [`RelayQuorumError`] when fewer than `min_relays`
+    /// relays accepted it. The error then carries a receipt that keeps the signed event,
+    /// so a retry resends it instead of publishing a new one.
+    fn into_receipt(self, min_relays: usize) -> Result<Receipt> {
+        let id = self.event.id.to_bech32()?;
+        let coordinate = self.event.kind.is_addressable().then(|| {
+            Coordinate::new(self.event.kind, self.event.pubkey)
+                .identifier(self.event.tags.identifier().unwrap_or_default())
+                .to_string()
+        });
+        if self.accepted.len() < min_relays {
+            let receipt = Receipt::Nostr {
+                id,
+                relays: self.accepted.clone(),
+                rejected: self.rejected.clone(),
+                event: Some(self.event.as_json()),
+                coordinate,
+            };
+            return Err(RelayQuorumError {
+                required: min_relays,
+                accepted: self.accepted,
+                rejected: self.rejected,
+                receipt: Some(receipt),
+            }
+            .into());
+        }
+        Ok(Receipt::Nostr {
+            id,
+            relays: self.accepted,
+            rejected: self.rejected,
+            event: None,
+            coordinate,
+        })
+    }
+}
+
+/// Signs `builder` and publishes it to `relays`, waiting for each relay's `OK`.
+async fn send(
+    signer: &Arc<dyn NostrSigner>,
+    relays: &[String],
+    builder: EventBuilder,
+) -> Result<Published> {
+    let event = builder.sign(signer).await.context("nostr: sign event")?;
+    broadcast(relays, event).await
+}
+
+/// Publishes the signed `event` to `relays`, waiting for each relay's `OK`.
+///
+/// Invalid URLs and relays that can't be reached are reported as rejections alongside
+/// relays that answer `OK false`.
+async fn broadcast(relays: &[String], event: Event) -> Result<Published> {
+    let client = Client::default();
+    let mut rejected = BTreeMap::new();
 
-    let builder = EventBuilder::text_note(text);
-    let output = client.send_event_builder(builder).await?;
+    for relay in relays {
+        match RelayUrl::parse(relay) {
+            Ok(url) => {
+                if let Err(e) = client.add_relay(url).await {
+                    rejected.insert(relay.clone(), e.to_string());
+                }
+            }
+            Err(e) => {
+                rejected.insert(relay.clone(), format!("invalid relay URL: {e}"));
+            }
+        }
+    }
+
+    let connected = client.try_connect(CONNECT_TIMEOUT).await;
+    for (url, reason) in connected.failed {
+        rejected.insert(url.to_string(), reason);
+    }
 
-    sleep(Duration::from_millis(300)).await;
+    let mut accepted = Vec::new();
+    if !connected.success.is_empty() {
+        let output = client.send_event_to(connected.success, &event).await?;
+        for (url, reason) in output.failed {
+            rejected.entry(url.to_string()).or_insert(reason);
+        }
+        accepted = output.success.iter().map(|url| url.to_string()).collect();
+        accepted.sort();
+    }
     client.disconnect().await;
 
-    Ok(output.id().to_bech32()?)
+    Ok(Published {
+        event,
+        accepted,
+        rejected,
+    })
+}
+
+/// Resends the event kept in an incomplete receipt to the write relays that have not
+/// accepted it yet, and merges the outcome into the receipt.
+async fn resend(
+    relays: &[String],
+    min_relays: usize,
+    accepted: &[String],
+    event: Event,
+) -> Result<Receipt> {
+    let missing: Vec<String> = relays
+        .iter()
+        .filter(|relay| !accepted.contains(relay))
+        .cloned()
+        .collect();
+    let mut published = broadcast(&missing, event).await?;
+    published.accepted.extend(accepted.iter().cloned());
+    published.accepted.sort();
+    published.accepted.dedup();
+    published.into_receipt(min_relays)
+}
+
+/// A text note for `post` with uploaded image URLs appended and their `imeta` tags.
+///
+/// Mentions are rewritten as NIP-27 `nostr:` references and tagged with `p`; hashtags
+/// and links get `t` and `r` tags. A post with a poll becomes a NIP-88 poll event whose
+/// responses are expected on `relays`.
+fn build_note(
+    post: &Post,
+    relays: &[String],
+    mentions: &[ResolvedMention],
+    attachments: Vec<(String, Tag)>,
+) -> Result<EventBuilder> {
+    let mut content = with_references(&post.text, mentions)?;
+    let mut tags = Vec::with_capacity(attachments.len() + 2);
+    if let Some(reply_to) = &post.reply_to {
+        tags.extend(build_reply_tags(reply_to)?);
+    }
+    // NIP-36: a content warning, or a bare one when the post is only marked sensitive.
+    if post.options.content_warning.is_some() || post.options.sensitive {
+        tags.push(Tag::from_standardized(TagStandard::ContentWarning {
+            reason: post.options.content_warning.clone(),
+        }));
+    }
+    // NIP-32 language labels, one per distinct primary language.
+    if !post.options.langs.is_empty() {
+        tags.push(Tag::from_standardized(TagStandard::LabelNamespace(
+            LANG_NAMESPACE.to_string(),
+        )));
+        let mut seen = Vec::new();
+        for code in post.options.langs.iter().map(|l| lang::primary_subtag(l)) {
+            let code = code.to_ascii_lowercase();
+            if !seen.contains(&code) {
+                tags.push(Tag::from_standardized(TagStandard::Label {
+                    value: code.clone(),
+                    namespace: Some(LANG_NAMESPACE.to_string()),
+                }));
+                seen.push(code);
+            }
+        }
+    }
+    for mention in mentions {
+        let tag = Tag::public_key(mention.profile.public_key);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    let links = detect_links(&post.text);
+    for hashtag in detect_hashtags(&post.text, &links) {
+        let tag = Tag::hashtag(&hashtag.tag);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    for link in &links {
+        let tag = Tag::reference(&link.url);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    for (url, imeta) in attachments {
+        content.push('\n');
+        content.push_str(&url);
+        tags.push(imeta);
+    }
+    let builder = match &post.poll {
+        Some(poll) => EventBuilder::poll(build_poll(poll, relays, content)?),
+        None => EventBuilder::text_note(content),
+    };
+    Ok(builder.tags(tags))
+}
+
+/// NIP-88 poll data; options are identified by their position.
+fn build_poll(poll: &Poll, relays: &[String], title: String) -> Result<nip88::Poll> {
+    let relays = relays
+        .iter()
+        .map(|relay| {
+            RelayUrl::parse(relay).with_context(|| format!("nostr: invalid relay URL {relay}"))
+        })
+        .collect::<Result<_>>()?;
+    Ok(nip88::Poll {
+        title,
+        r#type: if poll.multiple {
+            PollType::MultipleChoice
+        } else {
+            PollType::SingleChoice
+        },
+        options: poll
+            .options
+            .iter()
+            .enumerate()
+            .map(|(i, text)| PollOption {
+                id: i.to_string(),
+                text: text.clone(),
+            })
+            .collect(),
+        relays,
+        ends_at: Some(Timestamp::now() + poll.expires_in),
+    })
+}
+
+/// A NIP-23 long-form event with the article's metadata tags.
+fn build_article(article: &Article) -> Result<EventBuilder> {
+    let published_at = article
+        .published_at
+        .map(|at| Timestamp::from_secs(at.timestamp().max(0) as u64))
+        .unwrap_or_else(Timestamp::now);
+    let mut tags = vec![
+        Tag::identifier(article.identifier.clone()),
+        Tag::title(article.title.clone()),
+    ];
+    if let Some(summary) = &article.summary {
+        tags.push(Tag::from_standardized(TagStandard::Summary(
+            summary.clone(),
+        )));
+    }
+    if let Some(image) = &article.image {
+        let url = Url::parse(image).context("nostr: invalid article image URL")?;
+        tags.push(Tag::image(url, None));
+    }
+    tags.push(Tag::from_standardized(TagStandard::PublishedAt(
+        published_at,
+    )));
+    Ok(EventBuilder::long_form_text_note(article.content.clone()).tags(tags))
+}
+
+/// A pubkey referenced in the text, with the byte range of the reference.
+#[derive(Debug, Clone)]
+struct DetectedMention {
+    target: MentionTarget,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug, Clone)]
+enum MentionTarget {
+    /// An `npub` or `nprofile`, bare, `@`-prefixed or as a `nostr:` URI.
+    Profile(Nip19Profile),
+    /// An `@name@domain` NIP-05 identifier.
+    Nip05(String),
+}
+
+#[derive(Debug, Clone)]
+struct ResolvedMention {
+    profile: Nip19Profile,
+    start: usize,
+    end: usize,
+}
+
+/// Finds pubkey references and NIP-05 identifiers that start a word, outside links.
+fn detect_mentions(text: &str) -> Vec<DetectedMention> {
+    let links = detect_links(text);
+    let mut mentions = Vec::new();
+    let mut prev: Option<char> = None;
+
+    for (start, c) in text.char_indices() {
+        let at_boundary = starts_word(prev);
+        prev = Some(c);
+        if !at_boundary
+            || links
+                .iter()
+                .any(|link| start >= link.start && start < link.end)
+        {
+            continue;
+        }
+
+        let rest = &text[start..];
+        let (prefix, body) = if let Some(body) = rest.strip_prefix("nostr:") {
+            ("nostr:", body)
+        } else if let Some(body) = rest.strip_prefix('@') {
+            ("@", body)
+        } else {
+            ("", rest)
+        };
+
+        let (target, len) = if body.starts_with("npub1") || body.starts_with("nprofile1") {
+            let len = body
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(body.len());
+            let profile = match Nip19::from_bech32(&body[..len]) {
+                Ok(Nip19::Pubkey(public_key)) => Nip19Profile::new(public_key, []),
+                Ok(Nip19::Profile(profile)) => profile,
+                _ => continue,
+            };
+            (MentionTarget::Profile(profile), len)
+        } else if prefix == "@" {
+            let len = body
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '-' | '_')))
+                .unwrap_or(body.len());
+            let address = body[..len].trim_end_matches(['.', '-', '_']);
+            if !is_nip05_address(address) {
+                continue;
+            }
+            (
+                MentionTarget::Nip05(address.to_ascii_lowercase()),
+                address.len(),
+            )
+        } else {
+            continue;
+        };
+
+        mentions.push(DetectedMention {
+            target,
+            start,
+            end: start + prefix.len() + len,
+        });
+    }
+
+    mentions
+}
+
+/// Checks for `name@domain` with a dotted domain.
+fn is_nip05_address(address: &str) -> bool {
+    let Some((name, domain)) = address.split_once('@') else {
+        return false;
+    };
+    !name.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'))
+}
+
+/// Resolves each NIP-05 identifier to a pubkey; identifiers that don't resolve stay
+/// plain text.
+async fn resolve_mentions(
+    http: &reqwest::Client,
+    mentions: Vec<DetectedMention>,
+) -> Vec<ResolvedMention> {
+    let resolved = join_all(mentions.into_iter().map(|mention| async move {
+        let profile = match mention.target {
+            MentionTarget::Profile(profile) => profile,
+            MentionTarget::Nip05(address) => {
+                let profile = resolve_nip05(http, &address).await.ok()?;
+                Nip19Profile::new(
+                    profile.public_key,
+                    profile.relays.into_iter().take(MAX_RELAY_HINTS),
+                )
+            }
+        };
+        Some(ResolvedMention {
+            profile,
+            start: mention.start,
+            end: mention.end,
+        })
+    }))
+    .await;
+    resolved.into_iter().flatten().collect()
+}
+
+async fn resolve_nip05(http: &reqwest::Client, address: &str) -> Result<Nip05Profile> {
+    let address = Nip05Address::parse(address)?;
+    let resp = http
+        .get(address.url().as_str())
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await
+        .context("nostr: nostr.json request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("nostr: nostr.json", resp.status()).into());
+    }
+    let json: serde_json::Value = resp.json().await.context("nostr: parse nostr.json")?;
+    Ok(Nip05Profile::from_json(&address, &json)?)
+}
+
+/// Replaces each mention with its NIP-27 `nostr:npub` (or `nprofile`) reference.
+fn with_references(text: &str, mentions: &[ResolvedMention]) -> Result<String> {
+    let mut content = String::with_capacity(text.len());
+    let mut last = 0;
+    for mention in mentions {
+        content.push_str(&text[last..mention.start]);
+        content.push_str("nostr:");
+        if mention.profile.relays.is_empty() {
+            content.push_str(&mention.profile.public_key.to_bech32()?);
+        } else {
+            content.push_str(&mention.profile.to_bech32()?);
+        }
+        last = mention.end;
+    }
+    content.push_str(&text[last..]);
+    Ok(content)
+}
+
+/// NIP-10 marked `e` tags for a reply within a thread.
+fn build_reply_tags(reply_to: &ReplyTo) -> Result<Vec<Tag>> {
+    let root = event_id(&reply_to.root)?;
+    let parent = event_id(&reply_to.parent)?;
+
+    let mut tags = vec![Tag::parse(["e", &root.to_hex(), "", "root"])?];
+    if parent != root {
+        tags.push(Tag::parse(["e", &parent.to_hex(), "", "reply"])?);
+    }
+    Ok(tags)
+}
+
+fn event_id(receipt: &Receipt) -> Result<EventId> {
+    match receipt {
+        Receipt::Nostr { id, .. } => Ok(EventId::parse(id)?),
+        other => Err(anyhow!("nostr: cannot reply to {other:?}")),
+    }
+}
+
+async fn fetch_server_config(http: &reqwest::Client, server: &str) -> Result<ServerConfig> {
+    let server_url = Url::parse(server).context("nostr: invalid NOSTR_MEDIA_SERVER")?;
+    let config_url = nip96::get_server_config_url(&server_url)?;
+    let resp = http
+        .get(config_url.as_str())
+        .send()
+        .await
+        .context("nostr: nip96.json request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("nostr: nip96.json", resp.status()).into());
+    }
+    resp.json().await.context("nostr: parse nip96.json")
+}
+
+/// Uploads an image to a NIP-96 server and returns its URL with a NIP-92 `imeta` tag.
+async fn upload_image(
+    http: &reqwest::Client,
+    signer: &Arc<dyn NostrSigner>,
+    server_config: &ServerConfig,
+    image: &Image,
+) -> Result<(String, Tag)> {
+    let request = UploadRequest::new(signer, server_config, &image.data).await?;
+    let ext = image.mime_type.rsplit('/').next().unwrap_or("bin");
+    let file = Part::bytes(image.data.clone())
+        .file_name(format!("image.{ext}"))
+        .mime_str(&image.mime_type)
+        .context("nostr: media mime type")?;
+    let mut form = Form::new()
+        .part("file", file)
+        .text("content_type", image.mime_type.clone());
+    if !image.alt.is_empty() {
+        form = form.text("alt", image.alt.clone());
+    }
+
+    let resp = http
+        .post(request.url().as_str())
+        .header(AUTHORIZATION, request.authorization())
+        .multipart(form)
+        .send()
+        .await
+        .context("nostr: media upload failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError::new("nostr: media upload", resp.status()).into());
+    }
+    let upload: UploadResponse = resp.json().await.context("nostr: parse upload response")?;
+    let url = upload.download_url()?.to_string();
+
+    let mut fields = vec![format!("url {url}")];
+    if let Some(event) = &upload.nip94_event {
+        for tag in event.tags.iter() {
+            if let [key, value, ..] = tag.as_slice()
+                && key != "url"
+                && key != "alt"
+            {
+                fields.push(format!("{key} {value}"));
+            }
+        }
+    }
+    if !fields.iter().any(|f| f.starts_with("m ")) {
+        fields.push(format!("m {}", image.mime_type));
+    }
+    if !image.alt.is_empty() {
+        fields.push(format!("alt {}", image.alt));
+    }
+
+    Ok((url, Tag::custom(TagKind::custom("imeta"), fields)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use nostr_relay_builder::MockRelay;
+
+    use super::*;
+
+    /// Signs with local keys and counts the events it signed, standing in for a remote
+    /// signer.
+    #[derive(Debug)]
+    struct CountingSigner {
+        keys: Keys,
+        signed: AtomicUsize,
+    }
+
+    impl NostrSigner for CountingSigner {
+        fn backend(&self) -> SignerBackend<'_> {
+            SignerBackend::Custom("counting".into())
+        }
+
+        fn get_public_key(&self) -> BoxedFuture<'_, Result<PublicKey, SignerError>> {
+            self.keys.get_public_key()
+        }
+
+        fn sign_event(
+            &self,
+            unsigned: UnsignedEvent,
+        ) -> BoxedFuture<'_, Result<Event, SignerError>> {
+            self.signed.fetch_add(1, Ordering::SeqCst);
+            self.keys.sign_event(unsigned)
+        }
+
+        fn nip04_encrypt<'a>(
+            &'a self,
+            public_key: &'a PublicKey,
+            content: &'a str,
+        ) -> BoxedFuture<'a, Result<String, SignerError>> {
+            self.keys.nip04_encrypt(public_key, content)
+        }
+
+        fn nip04_decrypt<'a>(
+            &'a self,
+            public_key: &'a PublicKey,
+            encrypted_content: &'a str,
+        ) -> BoxedFuture<'a, Result<String, SignerError>> {
+            self.keys.nip04_decrypt(public_key, encrypted_content)
+        }
+
+        fn nip44_encrypt<'a>(
+            &'a self,
+            public_key: &'a PublicKey,
+            content: &'a str,
+        ) -> BoxedFuture<'a, Result<String, SignerError>> {
+            self.keys.nip44_encrypt(public_key, content)
+        }
+
+        fn nip44_decrypt<'a>(
+            &'a self,
+            public_key: &'a PublicKey,
+            payload: &'a str,
+        ) -> BoxedFuture<'a, Result<String, SignerError>> {
+            self.keys.nip44_decrypt(public_key, payload)
+        }
+    }
+
+    #[tokio::test]
+    async fn post_nostr_publishes_through_the_signer() {
+        let relay = MockRelay::run().await.unwrap();
+        let url = RelayUrl::parse(&relay.url()).unwrap().to_string();
+        let keys = Keys::generate();
+        let stand_in = Arc::new(CountingSigner {
+            keys: keys.clone(),
+            signed: AtomicUsize::new(0),
+        });
+        let signer: Arc<dyn NostrSigner> = stand_in.clone();
+
+        let receipt = post_nostr(
+            &signer,
+            std::slice::from_ref(&url),
+            1,
+            None,
+            &Post::new("Hello from a test"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stand_in.signed.load(Ordering::SeqCst), 1);
+        let Receipt::Nostr {
+            id,
+            relays,
+            rejected,
+            event,
+            coordinate,
+        } = receipt
+        else {
+            panic!("expected a Nostr receipt, got {receipt:?}");
+        };
+        assert_eq!(relays, [url.as_str()]);
+        assert!(rejected.is_empty());
+        assert!(event.is_none());
+        assert!(coordinate.is_none());
+
+        let client = Client::default();
+        client.add_relay(&url).await.unwrap();
+        client.connect().await;
+        let stored = client
+            .fetch_events(
+                Filter::new().id(EventId::parse(&id).unwrap()),
+                FETCH_TIMEOUT,
+            )
+            .await
+            .unwrap();
+        let stored = stored.first().expect("the relay stored the event");
+        assert_eq!(stored.pubkey, keys.public_key());
+        assert_eq!(stored.content, "Hello from a test");
+        assert!(stored.verify().is_ok());
+    }
+
+    #[tokio::test]
+    async fn article_deletion_requests_its_address() {
+        let relay = MockRelay::run().await.unwrap();
+        let url = RelayUrl::parse(&relay.url()).unwrap().to_string();
+        let keys = Keys::generate();
+        let signer: Arc<dyn NostrSigner> = Arc::new(keys.clone());
+
+        let article = Article::new("Release notes", "# Changes").with_identifier("notes");
+        let receipt = send(
+            &signer,
+            std::slice::from_ref(&url),
+            build_article(&article).unwrap(),
+        )
+        .await
+        .unwrap()
+        .into_receipt(1)
+        .unwrap();
+        let coordinate = format!("30023:{}:notes", keys.public_key().to_hex());
+        assert!(matches!(
+            &receipt,
+            Receipt::Nostr { coordinate: Some(c), .. } if *c == coordinate
+        ));
+
+        delete_nostr(&signer, &[], std::slice::from_ref(&receipt))
+            .await
+            .unwrap();
+
+        let client = Client::default();
+        client.add_relay(&url).await.unwrap();
+        client.connect().await;
+        let requests = client
+            .fetch_events(
+                Filter::new()
+                    .author(keys.public_key())
+                    .kind(Kind::EventDeletion),
+                FETCH_TIMEOUT,
+            )
+            .await
+            .unwrap();
+        let request = requests
+            .first()
+            .expect("the relay stored the deletion request");
+        let tags: Vec<_> = request.tags.iter().map(|tag| tag.as_slice()).collect();
+        assert!(tags.contains(&&["a".to_string(), coordinate][..]));
+    }
+
+    /// The detected mentions as `(npub or NIP-05 address, byte range)`.
+    fn targets(text: &str) -> Vec<(String, std::ops::Range<usize>)> {
+        detect_mentions(text)
+            .into_iter()
+            .map(|mention| {
+                let target = match mention.target {
+                    MentionTarget::Profile(profile) => profile.public_key.to_bech32().unwrap(),
+                    MentionTarget::Nip05(address) => address,
+                };
+                (target, mention.start..mention.end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn npub_and_nprofile_are_found_bare_prefixed_or_as_uris() {
+        let public_key = Keys::generate().public_key();
+        let npub = public_key.to_bech32().unwrap();
+        let relay = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let nprofile = Nip19Profile::new(public_key, [relay]).to_bech32().unwrap();
+        let text = format!("{npub} @{npub} nostr:{nprofile}");
+
+        let second = npub.len() + 1;
+        let third = second + npub.len() + 2;
+        assert_eq!(
+            targets(&text),
+            [
+                (npub.clone(), 0..npub.len()),
+                (npub.clone(), second..third - 1),
+                (npub, third..text.len()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_bech32_and_links_to_profiles_are_left_alone() {
+        let npub = Keys::generate().public_key().to_bech32().unwrap();
+        let text = format!("npub1notakey https://njump.me/{npub}");
+        assert!(targets(&text).is_empty());
+    }
+
+    #[test]
+    fn nip05_needs_an_at_sign_and_a_dotted_domain() {
+        assert_eq!(
+            targets("@Alice@Example.com, bob@example.com @carol@localhost"),
+            [("alice@example.com".to_string(), 0..18)]
+        );
+    }
+
+    #[test]
+    fn nostr_uris_keep_their_reference() {
+        let npub = Keys::generate().public_key().to_bech32().unwrap();
+        let text = format!("cc nostr:{npub}.");
+        assert_eq!(targets(&text), [(npub.clone(), 3..9 + npub.len())]);
+
+        let resolved: Vec<_> = detect_mentions(&text)
+            .into_iter()
+            .map(|mention| {
+                let MentionTarget::Profile(profile) = mention.target else {
+                    panic!("expected a profile");
+                };
+                ResolvedMention {
+                    profile,
+                    start: mention.start,
+                    end: mention.end,
+                }
+            })
+            .collect();
+        assert_eq!(with_references(&text, &resolved).unwrap(), text);
+    }
+
+    #[test]
+    fn resolved_mentions_become_nip27_references() {
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let relay = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let text = "こんにちは @alice@example.com と @bob@example.com さん";
+        let mention = |address: &str, profile| {
+            let start = text.find(address).unwrap();
+            ResolvedMention {
+                profile,
+                start,
+                end: start + address.len(),
+            }
+        };
+        // Bob's NIP-05 lists a relay, so the reference carries it as a hint.
+        let bob_profile = Nip19Profile::new(bob, [relay]);
+        let resolved = [
+            mention("@alice@example.com", Nip19Profile::new(alice, [])),
+            mention("@bob@example.com", bob_profile.clone()),
+        ];
+
+        assert_eq!(
+            with_references(text, &resolved).unwrap(),
+            format!(
+                "こんにちは nostr:{} と nostr:{} さん",
+                alice.to_bech32().unwrap(),
+                bob_profile.to_bech32().unwrap()
+            )
+        );
+    }
 }
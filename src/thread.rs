@@ -0,0 +1,177 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Splits `text` into numbered parts of at most `limit` graphemes each.
+///
+/// Breaks are preferred at paragraph boundaries, then sentences, then words; a single
+/// word longer than the budget is cut between graphemes. Text that already fits is
+/// returned unchanged as a single part without numbering.
+pub fn split_text(text: &str, limit: usize) -> Vec<String> {
+    if grapheme_len(text) <= limit {
+        return vec![text.to_string()];
+    }
+
+    // The " (i/n)" suffix grows with the number of digits in n, so retry with a wider
+    // reservation until the part count fits.
+    let mut digits = 1;
+    loop {
+        let budget = limit.saturating_sub(4 + 2 * digits).max(1);
+        let mut chunks = Vec::new();
+        pack(text, budget, Level::Paragraph, &mut chunks);
+
+        if chunks.len() < 10usize.pow(digits as u32) {
+            let total = chunks.len();
+            return chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| format!("{chunk} ({}/{total})", i + 1))
+                .collect();
+        }
+        digits += 1;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Level {
+    Paragraph,
+    Sentence,
+    Word,
+    Grapheme,
+}
+
+impl Level {
+    fn pieces(self, text: &str) -> Vec<&str> {
+        match self {
+            Level::Paragraph => text.split_inclusive("\n\n").collect(),
+            Level::Sentence => text.split_sentence_bounds().collect(),
+            Level::Word => text.split_word_bounds().collect(),
+            Level::Grapheme => text.graphemes(true).collect(),
+        }
+    }
+
+    fn finer(self) -> Self {
+        match self {
+            Level::Paragraph => Level::Sentence,
+            Level::Sentence => Level::Word,
+            Level::Word | Level::Grapheme => Level::Grapheme,
+        }
+    }
+}
+
+fn pack(text: &str, budget: usize, level: Level, out: &mut Vec<String>) {
+    let mut current = String::new();
+
+    for piece in level.pieces(text) {
+        let candidate = format!("{current}{piece}");
+        if grapheme_len(candidate.trim()) <= budget {
+            current = candidate;
+            continue;
+        }
+
+        flush(&mut current, out);
+        if grapheme_len(piece.trim()) <= budget {
+            current.push_str(piece);
+        } else {
+            pack(piece, budget, level.finer(), out);
+        }
+    }
+
+    flush(&mut current, out);
+}
+
+fn flush(current: &mut String, out: &mut Vec<String>) {
+    let trimmed = current.trim();
+    if !trimmed.is_empty() {
+        out.push(trimmed.to_string());
+    }
+    current.clear();
+}
+
+pub(crate) fn grapheme_len(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_within(parts: &[String], limit: usize) {
+        for part in parts {
+            assert!(grapheme_len(part) <= limit, "{part:?} exceeds {limit}");
+        }
+    }
+
+    #[test]
+    fn text_that_fits_is_unchanged() {
+        assert_eq!(split_text("short post", 20), vec!["short post"]);
+    }
+
+    #[test]
+    fn prefers_paragraph_breaks() {
+        let text = "First paragraph here.\n\nSecond paragraph here.";
+        let parts = split_text(text, 30);
+        assert_eq!(
+            parts,
+            vec![
+                "First paragraph here. (1/2)",
+                "Second paragraph here. (2/2)"
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_sentences() {
+        let text = "One sentence here. Another one here.";
+        let parts = split_text(text, 26);
+        assert_eq!(
+            parts,
+            vec!["One sentence here. (1/2)", "Another one here. (2/2)"]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_words() {
+        let text = "alpha beta gamma delta epsilon zeta";
+        let parts = split_text(text, 18);
+        assert_eq!(
+            parts,
+            vec![
+                "alpha beta (1/3)",
+                "gamma delta (2/3)",
+                "epsilon zeta (3/3)"
+            ]
+        );
+    }
+
+    #[test]
+    fn cuts_long_words_between_graphemes() {
+        // Flags are two code points each and must stay whole.
+        let text = "🇯🇵".repeat(12);
+        let parts = split_text(&text, 10);
+        assert_eq!(
+            parts,
+            vec!["🇯🇵🇯🇵🇯🇵🇯🇵 (1/3)", "🇯🇵🇯🇵🇯🇵🇯🇵 (2/3)", "🇯🇵🇯🇵🇯🇵🇯🇵 (3/3)"]
+        );
+        assert_within(&parts, 10);
+    }
+
+    #[test]
+    fn nine_parts_use_a_one_digit_suffix() {
+        let text = ["aaaa"; 9].join(" ");
+        let parts = split_text(&text, 12);
+        assert_eq!(parts.len(), 9);
+        assert_eq!(parts[8], "aaaa (9/9)");
+        assert_within(&parts, 12);
+    }
+
+    #[test]
+    fn ten_parts_reserve_room_for_two_digits() {
+        // With a one-digit reservation each word fills a part, giving ten parts; the
+        // wider "(10/10)" suffix must still fit.
+        let text = ["aaaa"; 10].join(" ");
+        let parts = split_text(&text, 12);
+        assert_eq!(parts.len(), 10);
+        assert_eq!(parts[0], "aaaa (1/10)");
+        assert_eq!(parts[9], "aaaa (10/10)");
+        assert_within(&parts, 12);
+    }
+}